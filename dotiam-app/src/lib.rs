@@ -1,7 +1,42 @@
-use dotiam_core::{GameState, WorldTemplate};
+use dotiam_core::{Diagnostic, GameState, WorldTemplate};
 use sqlx::sqlite::SqlitePool;
+use std::fmt;
 use uuid::Uuid;
 
+#[derive(Debug)]
+pub enum RepositoryError {
+    Database(sqlx::Error),
+    /// The template failed `WorldTemplate::validate`; holds the error diagnostics.
+    InvalidWorld(Vec<Diagnostic>),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::Database(err) => write!(f, "database error: {}", err),
+            RepositoryError::InvalidWorld(diagnostics) => {
+                writeln!(f, "invalid world template:")?;
+                for diagnostic in diagnostics {
+                    writeln!(f, "  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(err: sqlx::Error) -> Self {
+        RepositoryError::Database(err)
+    }
+}
+
+fn random_seed() -> u64 {
+    Uuid::new_v4().as_u64_pair().0
+}
+
 pub struct Repository {
     pool: SqlitePool,
 }
@@ -11,17 +46,32 @@ impl Repository {
         Self { pool }
     }
 
-    pub async fn create_run(&self, player_name: String) -> Result<String, sqlx::Error> {
+    /// Starts a run in the built-in demo world. Pass a seed to replay a run
+    /// exactly; otherwise a random one is picked.
+    pub async fn create_run(&self, player_name: String, seed: Option<u64>) -> Result<String, sqlx::Error> {
         let id = Uuid::new_v4().to_string();
-        let state = GameState::new(player_name.clone());
+        let mut state = GameState::new(player_name.clone());
+        state.reseed(seed.unwrap_or_else(random_seed));
         self.insert_run(&id, &player_name, &state).await?;
         Ok(id)
     }
 
-    pub async fn create_run_from_template(&self, player_name: String, template: WorldTemplate) -> Result<String, sqlx::Error> {
+    /// Like `create_run`, in a world loaded from a template.
+    pub async fn create_run_from_template(
+        &self,
+        player_name: String,
+        template: WorldTemplate,
+        seed: Option<u64>,
+    ) -> Result<String, RepositoryError> {
+        let errors: Vec<Diagnostic> = template.validate().into_iter().filter(|d| d.is_error()).collect();
+        if !errors.is_empty() {
+            return Err(RepositoryError::InvalidWorld(errors));
+        }
+
         let id = Uuid::new_v4().to_string();
         let world = template.to_world();
-        let state = GameState::new_with_world(player_name.clone(), world);
+        let mut state = GameState::new_with_world(player_name.clone(), world);
+        state.reseed(seed.unwrap_or_else(random_seed));
         self.insert_run(&id, &player_name, &state).await?;
         Ok(id)
     }
@@ -0,0 +1,671 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::{Feature, GameAction, GameEvent, GameState, GameStatus, Node, UseTarget, World, WorldTemplate};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AnalysisOptions {
+    /// Exploration stops after this many distinct states and the report is marked truncated.
+    pub max_states: usize,
+    /// Also try dropping every carried item everywhere and putting it into every open
+    /// container. This finds softlocks caused by leaving items behind, but multiplies
+    /// the state space considerably.
+    pub include_drops: bool,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            max_states: 50_000,
+            include_drops: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProblemKind {
+    UnreachableNode(String),
+    UnobtainableItem(String),
+    /// Index into `WorldTemplate::combinations`.
+    UnusableCombination(usize),
+    /// No reachable state wins the game.
+    VictoryUnreachable,
+    /// After the witness path, winning is no longer possible.
+    Softlock,
+    /// The last action of the witness path rolls the dice: a `Random` or `RollTable`
+    /// effect, a loot chance or a combat roll. Only what the start's seed happens to
+    /// roll is explored, so problems behind other outcomes can be missed.
+    RandomOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Shortest sequence of actions from the start that demonstrates the problem.
+    /// For problems about something that never happens, it leads to the closest the
+    /// game gets: next to an edge into the unreachable node, where the unobtainable
+    /// item was seen, holding most of a combination's items, or meeting most
+    /// conditions of a victory. It is None when not even that happens.
+    pub witness: Option<Vec<GameAction>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AnalysisReport {
+    pub states_explored: usize,
+    pub truncated: bool,
+    pub problems: Vec<Problem>,
+}
+
+struct Explored {
+    parent: Option<(usize, GameAction)>,
+    successors: Vec<usize>,
+    status: GameStatus,
+}
+
+impl GameState {
+    /// The actions `analyze` and `solve` try from this state: `candidate_actions`,
+    /// and with `options.include_drops` also dropping every carried item and putting
+    /// it into every open container.
+    pub fn search_actions(&self, options: &AnalysisOptions) -> Vec<GameAction> {
+        let mut actions = self.candidate_actions();
+        if options.include_drops {
+            actions.extend(self.player.inventory.ids().map(|id| GameAction::Drop(id.clone(), None)));
+            for (container_id, _) in self.reachable_containers().into_iter().filter(|(_, c)| c.open) {
+                for item_id in self.player.inventory.ids().filter(|id| **id != container_id) {
+                    actions.push(GameAction::PutIn(item_id.clone(), container_id.clone()));
+                }
+            }
+        }
+        actions
+    }
+
+    /// Actions worth trying from this state: every edge, every item here and in the
+    /// inventory, features hiding secrets or with use rules of their own, opening
+    /// and emptying containers, combinations and the targets named by item use rules.
+    pub fn candidate_actions(&self) -> Vec<GameAction> {
+        let mut actions = Vec::new();
+        let Some(node) = self.world.nodes.get(&self.player.current_node) else {
+            return actions;
+        };
+
+        for edge in &node.edges {
+            actions.push(GameAction::Move(edge.target_id.clone()));
+        }
+        for item_id in node.items.ids() {
+            actions.push(GameAction::Pickup(item_id.clone(), None));
+        }
+        actions.push(GameAction::Explore(None));
+        let features = node
+            .edges
+            .iter()
+            .filter_map(|e| e.hidden.as_ref().and_then(|d| d.feature.as_ref()))
+            .chain(node.hidden_items.iter().filter_map(|h| h.feature.as_ref()));
+        for feature in features {
+            let action = GameAction::Explore(Some(feature.clone()));
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        let mut scenery: Vec<&Feature> = node.features.values().collect();
+        scenery.sort_by(|a, b| a.id.cmp(&b.id));
+        for feature in scenery {
+            for rule in &feature.on_use {
+                let action = match &rule.item {
+                    None => GameAction::Use(feature.id.clone(), None),
+                    Some(item_id) if self.player.inventory.contains(item_id) => {
+                        GameAction::Use(item_id.clone(), Some(feature.id.clone()))
+                    }
+                    Some(_) => continue,
+                };
+                if !actions.contains(&action) {
+                    actions.push(action);
+                }
+            }
+        }
+
+        for (container_id, container) in self.reachable_containers() {
+            if container.open {
+                for item_id in container.contents.ids() {
+                    actions.push(GameAction::TakeFrom(item_id.clone(), container_id.clone()));
+                }
+            } else if !container.locked || self.check_conditions(&container.unlock_conditions) {
+                actions.push(GameAction::Open(container_id));
+            }
+        }
+
+        let inventory = &self.player.inventory;
+        let reachable = self.reachable_items();
+        for combination in &self.world.combinations {
+            let has_ingredients = combination.ingredients.iter().all(|s| inventory.count(&s.item_id) >= s.count);
+            if has_ingredients && combination.tools.iter().all(|t| reachable.contains(t)) {
+                actions.push(GameAction::Combine(combination.item_ids()));
+            }
+        }
+        for item_id in inventory.ids() {
+            let Some(item) = self.world.items.get(item_id) else {
+                continue;
+            };
+            for rule in &item.use_rules {
+                let target = match &rule.target {
+                    None => None,
+                    Some(UseTarget::Item(target)) | Some(UseTarget::Feature(target)) | Some(UseTarget::Node(target)) => {
+                        Some(target.clone())
+                    }
+                };
+                let action = GameAction::Use(item_id.clone(), target);
+                if !actions.contains(&action) {
+                    actions.push(action);
+                }
+            }
+            let consume = GameAction::Use(item_id.clone(), None);
+            if item.is_consumable() && !actions.contains(&consume) {
+                actions.push(consume);
+            }
+        }
+
+        for npc in self.npcs_here() {
+            if npc.dialogue.contains_key("start") {
+                actions.push(GameAction::Talk(npc.id.clone()));
+            }
+            if npc.combat.is_some() {
+                actions.push(GameAction::Attack(npc.id.clone(), None));
+            }
+        }
+        if !self.hostiles_here().is_empty() {
+            actions.push(GameAction::Flee);
+        }
+        for number in 1..=self.dialogue_choices().len() {
+            actions.push(GameAction::Say(number));
+        }
+        actions
+    }
+
+    /// A copy of the state suitable for searching: no log and no undo history.
+    pub(crate) fn search_root(&self) -> GameState {
+        let mut state = self.clone();
+        state.log.clear();
+        state.history.clear();
+        state.history_limit = 0;
+        state
+    }
+}
+
+/// Identifies search states by everything that can influence future play. The turn
+/// counter, log, undo history and journal order are ignored, visit counts only
+/// distinguish "once" from "more", and item lists are treated as unordered. The key
+/// is the canonical JSON of the rest, so different states never share a key; to keep
+/// it short, fields of the player, nodes, items and NPCs that still match the start
+/// are left out. The random number generator is left out as well, so states only
+/// differing in what will be rolled next are one state.
+pub(crate) struct StateKeys {
+    start: World,
+    start_player: Value,
+    start_nodes: HashMap<String, Value>,
+    start_items: HashMap<String, Value>,
+    start_npcs: HashMap<String, Value>,
+}
+
+impl StateKeys {
+    pub(crate) fn new(start: &GameState) -> Self {
+        let world = &start.world;
+        Self {
+            start: world.clone(),
+            start_player: player_value(start),
+            start_nodes: world.nodes.iter().map(|(id, node)| (id.clone(), node_value(node))).collect(),
+            start_items: world.items.iter().map(|(id, item)| (id.clone(), json!(item))).collect(),
+            start_npcs: world.npcs.iter().map(|(id, npc)| (id.clone(), json!(npc))).collect(),
+        }
+    }
+
+    pub(crate) fn key(&self, state: &GameState) -> String {
+        let world = json!({
+            "nodes": changed_entries(&state.world.nodes, &self.start.nodes, &self.start_nodes, node_value),
+            "items": changed_entries(&state.world.items, &self.start.items, &self.start_items, |item| json!(item)),
+            "combinations": (state.world.combinations != self.start.combinations).then_some(&state.world.combinations),
+            "npcs": changed_entries(&state.world.npcs, &self.start.npcs, &self.start_npcs, |npc| json!(npc)),
+            "flags": state.world.flags,
+        });
+        let player = changed_fields(player_value(state), Some(&self.start_player));
+
+        // serde_json::Value keeps object keys sorted, which makes HashMaps canonical.
+        json!([player, world, state.status, state.ending]).to_string()
+    }
+}
+
+fn player_value(state: &GameState) -> Value {
+    let mut player = state.player.clone();
+    for count in player.visits.values_mut() {
+        *count = (*count).min(2);
+    }
+    player.inventory.sort();
+    // The journal only orders the visited nodes, which `visits` already covers.
+    player.journal.clear();
+    // Where the player came from only matters for fleeing a fight.
+    if state.hostiles_here().is_empty() {
+        player.came_from = None;
+    }
+    json!(player)
+}
+
+fn node_value(node: &Node) -> Value {
+    let mut node = node.clone();
+    node.items.sort();
+    json!(node)
+}
+
+/// `value` without the fields it shares with `start`.
+fn changed_fields(value: Value, start: Option<&Value>) -> Value {
+    match (value, start) {
+        (Value::Object(mut fields), Some(Value::Object(start))) => {
+            fields.retain(|name, field| start.get(name) != Some(field));
+            Value::Object(fields)
+        }
+        (value, _) => value,
+    }
+}
+
+/// The changed fields of every entry that differs from the start other than in order.
+fn changed_entries<'a, T: PartialEq>(
+    entries: &'a HashMap<String, T>,
+    start: &HashMap<String, T>,
+    start_values: &HashMap<String, Value>,
+    to_value: impl Fn(&T) -> Value,
+) -> BTreeMap<&'a String, Value> {
+    entries
+        .iter()
+        .filter(|(id, entry)| start.get(*id) != Some(entry))
+        .map(|(id, entry)| (id, changed_fields(to_value(entry), start_values.get(id))))
+        .filter(|(_, fields)| fields.as_object().is_none_or(|f| !f.is_empty()))
+        .collect()
+}
+
+fn witness(explored: &[Explored], mut index: usize) -> Vec<GameAction> {
+    let mut path = Vec::new();
+    while let Some((parent, action)) = &explored[index].parent {
+        path.push(action.clone());
+        index = *parent;
+    }
+    path.reverse();
+    path
+}
+
+/// Explores every state reachable from the start of `template` with the real game
+/// rules and reports what can never happen and where the game can no longer be won.
+/// Conditions on the turn counter are evaluated, but states differing only in the
+/// turn are treated as the same state. Random effects are not branched over: each
+/// roll takes the outcome the seed gives, and the first action that rolls is
+/// reported as `RandomOutcome` so the report is not mistaken for a complete one.
+pub fn analyze(template: &WorldTemplate, options: &AnalysisOptions) -> AnalysisReport {
+    let start = GameState::new_with_world("Analyzer".to_string(), template.to_world()).search_root();
+
+    let mut explored: Vec<Explored> = Vec::new();
+    let mut index_of: HashMap<String, usize> = HashMap::new();
+    let mut queue: VecDeque<(usize, GameState)> = VecDeque::new();
+
+    let mut nodes_seen: HashMap<String, usize> = HashMap::new();
+    let mut items_held: HashSet<String> = HashSet::new();
+    let mut combinations_used: HashSet<usize> = HashSet::new();
+    let mut first_roll: Option<Vec<GameAction>> = None;
+    let mut truncated = false;
+
+    // The first, and so nearest, state that came closest to what never happens
+    let mut next_to_node: HashMap<String, usize> = HashMap::new();
+    let mut near_item: HashMap<String, usize> = HashMap::new();
+    let mut most_at_hand: Vec<(usize, usize)> = vec![(0, 0); template.combinations.len()];
+    let mut most_met: (usize, usize) = (0, 0);
+
+    let keys = StateKeys::new(&start);
+    index_of.insert(keys.key(&start), 0);
+    explored.push(Explored { parent: None, successors: Vec::new(), status: start.status.clone() });
+    queue.push_back((0, start));
+
+    while let Some((index, state)) = queue.pop_front() {
+        nodes_seen.entry(state.player.current_node.clone()).or_insert(index);
+        items_held.extend(state.player.inventory.ids().cloned());
+        if let Some(node) = state.world.nodes.get(&state.player.current_node) {
+            for edge in &node.edges {
+                next_to_node.entry(edge.target_id.clone()).or_insert(index);
+            }
+            for item_id in node.items.ids().chain(node.hidden_items.iter().map(|h| &h.item_id)) {
+                near_item.entry(item_id.clone()).or_insert(index);
+            }
+        }
+        for item_id in &state.player.seen_items {
+            near_item.entry(item_id.clone()).or_insert(index);
+        }
+        // Ingredients count when carried, tools also when lying at hand
+        let at_hand = state.reachable_items();
+        for (i, combination) in template.combinations.iter().enumerate() {
+            let carried = combination.ingredients.iter().filter(|s| state.player.inventory.contains(&s.item_id)).count();
+            let count = carried + combination.tools.iter().filter(|id| at_hand.contains(id)).count();
+            if count > most_at_hand[i].0 {
+                most_at_hand[i] = (count, index);
+            }
+        }
+        let met = template.victory.iter().map(|e| e.conditions.iter().filter(|c| state.check_condition(c)).count()).max();
+        if met.unwrap_or(0) > most_met.0 {
+            most_met = (met.unwrap_or(0), index);
+        }
+        if state.is_over() {
+            continue;
+        }
+
+        for action in state.search_actions(options) {
+            let mut next = state.clone();
+            let events = next.apply_action(action.clone());
+            next.log.clear();
+            if first_roll.is_none() && next.rng != state.rng {
+                let mut path = witness(&explored, index);
+                path.push(action.clone());
+                first_roll = Some(path);
+            }
+            for event in &events {
+                if let GameEvent::Combined { combination, .. } = event {
+                    combinations_used.insert(*combination);
+                }
+            }
+
+            let key = keys.key(&next);
+            let next_index = match index_of.get(&key) {
+                Some(existing) => *existing,
+                None => {
+                    if explored.len() >= options.max_states {
+                        truncated = true;
+                        continue;
+                    }
+                    let next_index = explored.len();
+                    index_of.insert(key, next_index);
+                    explored.push(Explored {
+                        parent: Some((index, action)),
+                        successors: Vec::new(),
+                        status: next.status.clone(),
+                    });
+                    queue.push_back((next_index, next));
+                    next_index
+                }
+            };
+            if next_index != index && !explored[index].successors.contains(&next_index) {
+                explored[index].successors.push(next_index);
+            }
+        }
+    }
+
+    let mut problems = Vec::new();
+
+    let mut node_ids: Vec<&String> = template.nodes.keys().collect();
+    node_ids.sort();
+    for node_id in node_ids {
+        if !nodes_seen.contains_key(node_id) {
+            let witness = next_to_node.get(node_id).map(|index| witness(&explored, *index));
+            problems.push(Problem { kind: ProblemKind::UnreachableNode(node_id.clone()), witness });
+        }
+    }
+
+    let mut item_ids: Vec<&String> = template.items.iter().filter(|(_, i)| i.can_pickup).map(|(id, _)| id).collect();
+    item_ids.sort();
+    for item_id in item_ids {
+        if !items_held.contains(item_id) {
+            let witness = near_item.get(item_id).map(|index| witness(&explored, *index));
+            problems.push(Problem { kind: ProblemKind::UnobtainableItem(item_id.clone()), witness });
+        }
+    }
+
+    for (i, (count, index)) in most_at_hand.into_iter().enumerate() {
+        if !combinations_used.contains(&i) {
+            let witness = (count > 0).then(|| witness(&explored, index));
+            problems.push(Problem { kind: ProblemKind::UnusableCombination(i), witness });
+        }
+    }
+
+    if let Some(path) = first_roll {
+        problems.push(Problem { kind: ProblemKind::RandomOutcome, witness: Some(path) });
+    }
+
+    let has_victory = !template.victory.is_empty() || explored.iter().any(|e| e.status == GameStatus::Won);
+    if has_victory {
+        let closest = (most_met.0 > 0).then(|| witness(&explored, most_met.1));
+        problems.extend(softlocks(&explored, truncated, closest));
+    }
+
+    AnalysisReport {
+        states_explored: explored.len(),
+        truncated,
+        problems,
+    }
+}
+
+/// Finds the points of no return: playing states that cannot reach a win although
+/// the state before them could. Without any winning state it reports
+/// `VictoryUnreachable` with `closest_to_victory` as the witness. Every such state is reported with the shortest path
+/// leading to it, so the same fatal action taken in two different situations, such as
+/// dropping the key in two different rooms, gives two problems.
+fn softlocks(explored: &[Explored], truncated: bool, closest_to_victory: Option<Vec<GameAction>>) -> Vec<Problem> {
+    let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); explored.len()];
+    for (index, state) in explored.iter().enumerate() {
+        for successor in &state.successors {
+            predecessors[*successor].push(index);
+        }
+    }
+
+    let mut can_win = vec![false; explored.len()];
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    for (index, state) in explored.iter().enumerate() {
+        if state.status == GameStatus::Won {
+            can_win[index] = true;
+            queue.push_back(index);
+        }
+    }
+    if queue.is_empty() {
+        return vec![Problem { kind: ProblemKind::VictoryUnreachable, witness: closest_to_victory }];
+    }
+    while let Some(index) = queue.pop_front() {
+        for predecessor in &predecessors[index] {
+            if !can_win[*predecessor] {
+                can_win[*predecessor] = true;
+                queue.push_back(*predecessor);
+            }
+        }
+    }
+
+    // A truncated search has unexplored successors, so "cannot win" is not proven.
+    if truncated {
+        return Vec::new();
+    }
+
+    // Each explored state has exactly one recorded (parent, action), so there is
+    // nothing to deduplicate: distinct problems are distinct states.
+    let mut problems = Vec::new();
+    for (index, state) in explored.iter().enumerate() {
+        if can_win[index] || state.status != GameStatus::Playing {
+            continue;
+        }
+        let Some((parent, _)) = &state.parent else {
+            continue;
+        };
+        if can_win[*parent] {
+            problems.push(Problem { kind: ProblemKind::Softlock, witness: Some(witness(explored, index)) });
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Condition, ConditionType};
+
+    #[test]
+    fn test_world_yaml_has_no_problems() {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        // Bounded like the solver test, so that a world grown past what the default
+        // run can afford shows up as truncated instead of slowing every run down
+        let options = AnalysisOptions { max_states: 10_000, ..AnalysisOptions::default() };
+        let report = analyze(&template, &options);
+        assert!(!report.truncated);
+        assert_eq!(report.problems, vec![]);
+    }
+
+    #[test]
+    fn test_search_actions_with_drops() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A porch."
+    attributes: {}
+    edges: []
+    items: [bag, coin]
+items:
+  bag: { id: bag, name: "Bag", description: "A bag.", can_pickup: true, container: { open: true } }
+  coin: { id: coin, name: "Coin", description: "A coin.", can_pickup: true }
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(GameAction::Pickup("bag".to_string(), None));
+        state.apply_action(GameAction::Pickup("coin".to_string(), None));
+
+        let plain = state.search_actions(&AnalysisOptions::default());
+        assert!(!plain.iter().any(|a| matches!(a, GameAction::Drop(..) | GameAction::PutIn(..))));
+        let options = AnalysisOptions { include_drops: true, ..AnalysisOptions::default() };
+        let actions = state.search_actions(&options);
+        assert!(actions.contains(&GameAction::Drop("coin".to_string(), None)));
+        assert!(actions.contains(&GameAction::PutIn("coin".to_string(), "bag".to_string())));
+        assert!(!actions.contains(&GameAction::PutIn("bag".to_string(), "bag".to_string())));
+    }
+
+    #[test]
+    fn test_state_keys() {
+        let start = GameState::new("Tester".to_string()).search_root();
+        let keys = StateKeys::new(&start);
+        let trips = |count| {
+            let mut state = start.clone();
+            for _ in 0..count {
+                state.apply_action(GameAction::Move("forest".to_string()));
+                state.apply_action(GameAction::Move("start".to_string()));
+            }
+            keys.key(&state)
+        };
+        // Only "once" and "more" visits count, and the turn does not
+        assert_ne!(trips(0), trips(1));
+        assert_ne!(trips(1), trips(2));
+        assert_eq!(trips(2), trips(3));
+
+        // What the generator rolls next does not make a state different
+        let mut reseeded = start.clone();
+        reseeded.reseed(7);
+        assert_eq!(keys.key(&reseeded), keys.key(&start));
+    }
+
+    #[test]
+    fn test_analyze_finds_problems() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "Start"
+    attributes: {}
+    edges:
+      - target_id: pit
+        label: "Jump into the pit"
+        conditions: []
+      - target_id: tower
+        label: "Tower"
+        conditions:
+          - !HasItem wings
+    items: [rope, stone]
+  pit:
+    id: pit
+    description: "A pit with no way out"
+    attributes: {}
+    edges: []
+    items: []
+  tower:
+    id: tower
+    description: "Tower"
+    attributes: {}
+    edges: []
+    items: []
+  exit:
+    id: exit
+    description: "Exit"
+    attributes: {}
+    edges: []
+    items: []
+items:
+  rope:
+    id: rope
+    name: "Rope"
+    description: "Rope"
+    can_pickup: true
+  stone:
+    id: stone
+    name: "Stone"
+    description: "Stone"
+    can_pickup: true
+  wings:
+    id: wings
+    name: "Wings"
+    description: "Wings"
+    can_pickup: true
+  feather:
+    id: feather
+    name: "Feather"
+    description: "Feather"
+    can_pickup: true
+combinations:
+  - item1: feather
+    item2: rope
+    result: wings
+victory:
+  - conditions:
+      - !HasItem stone
+      - !HasItem rope
+    message: "You win"
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let report = analyze(&template, &AnalysisOptions::default());
+        let kinds: Vec<&ProblemKind> = report.problems.iter().map(|p| &p.kind).collect();
+
+        assert!(kinds.contains(&&ProblemKind::UnreachableNode("tower".to_string())));
+        assert!(kinds.contains(&&ProblemKind::UnreachableNode("exit".to_string())));
+        assert!(kinds.contains(&&ProblemKind::UnobtainableItem("wings".to_string())));
+        assert!(kinds.contains(&&ProblemKind::UnusableCombination(0)));
+        assert!(!kinds.contains(&&ProblemKind::UnreachableNode("pit".to_string())));
+        assert!(!kinds.contains(&&ProblemKind::RandomOutcome));
+
+        // Witnesses lead to the closest the game gets to what never happens
+        let witness = |kind: ProblemKind| report.problems.iter().find(|p| p.kind == kind).unwrap().witness.clone();
+        assert_eq!(witness(ProblemKind::UnreachableNode("tower".to_string())), Some(vec![]));
+        assert_eq!(witness(ProblemKind::UnreachableNode("exit".to_string())), None);
+        assert_eq!(witness(ProblemKind::UnobtainableItem("wings".to_string())), None);
+        assert_eq!(
+            witness(ProblemKind::UnusableCombination(0)),
+            Some(vec![GameAction::Pickup("rope".to_string(), None)])
+        );
+
+        let mut unwinnable = template.clone();
+        unwinnable.victory[0].conditions.push(Condition { condition_type: ConditionType::HasItem("wings".to_string()) });
+        let unwinnable = analyze(&unwinnable, &AnalysisOptions::default());
+        let victory = unwinnable.problems.iter().find(|p| p.kind == ProblemKind::VictoryUnreachable).unwrap();
+        let stone_and_rope = [GameAction::Pickup("rope".to_string(), None), GameAction::Pickup("stone".to_string(), None)];
+        assert_eq!(victory.witness, Some(stone_and_rope.to_vec()));
+
+        let softlock = report.problems.iter().find(|p| p.kind == ProblemKind::Softlock).unwrap();
+        assert_eq!(softlock.witness, Some(vec![GameAction::Move("pit".to_string())]));
+        // The same jump made while carrying the rope is a dead end of its own
+        assert!(report.problems.contains(&Problem {
+            kind: ProblemKind::Softlock,
+            witness: Some(vec![GameAction::Pickup("rope".to_string(), None), GameAction::Move("pit".to_string())]),
+        }));
+
+        // Replaying the witness really leaves the player stuck
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        for action in softlock.witness.clone().unwrap() {
+            state.apply_action(action);
+        }
+        assert_eq!(state.player.current_node, "pit");
+        assert!(state.candidate_actions().iter().all(|a| matches!(a, GameAction::Explore(_))));
+    }
+}
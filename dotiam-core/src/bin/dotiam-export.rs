@@ -0,0 +1,40 @@
+//! Prints a world file as Graphviz DOT, a Mermaid flowchart or normalized YAML.
+//!
+//! Usage: dotiam-export <world.yaml> [dot|mermaid|yaml]
+
+use dotiam_core::WorldTemplate;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, format) = match args.as_slice() {
+        [path] => (path, "dot"),
+        [path, format] => (path, format.as_str()),
+        _ => {
+            eprintln!("Usage: dotiam-export <world.yaml> [dot|mermaid|yaml]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let template = match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|content| {
+        WorldTemplate::from_yaml(&content).map_err(|e| e.to_string())
+    }) {
+        Ok(template) => template,
+        Err(e) => {
+            eprintln!("Cannot load {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let output = match format {
+        "dot" => template.to_dot(),
+        "mermaid" => template.to_mermaid(),
+        "yaml" => template.to_yaml(),
+        other => {
+            eprintln!("Unknown format: {} (expected dot, mermaid or yaml)", other);
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", output);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Effect, GameEvent, GameState, ItemStacks, Npc};
+
+/// Attack of the player's bare hands.
+const FIST_ATTACK: u32 = 1;
+
+/// Makes an NPC something the player can fight.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CombatStats {
+    /// Current HP; the NPC is defeated at 0.
+    pub hp: u32,
+    #[serde(default)]
+    pub attack: u32,
+    #[serde(default)]
+    pub defense: u32,
+    #[serde(default)]
+    pub behaviour: Behaviour,
+    /// Dropped into the node when the NPC is defeated.
+    #[serde(default)]
+    pub loot: Vec<Loot>,
+    #[serde(default)]
+    pub on_defeat: Vec<Effect>,
+    /// Set once the player has attacked it.
+    #[serde(default)]
+    pub provoked: bool,
+}
+
+/// When an NPC strikes at the player, at the end of every turn they share a node.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Behaviour {
+    /// Attacks on sight.
+    Aggressive,
+    /// Fights back once attacked.
+    #[default]
+    Defensive,
+    /// Never fights back, like a training dummy.
+    Passive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Loot {
+    pub item_id: String,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// Percent chance that it drops.
+    #[serde(default = "default_chance")]
+    pub chance: u32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_chance() -> u32 {
+    100
+}
+
+impl Npc {
+    pub fn is_defeated(&self) -> bool {
+        self.combat.as_ref().is_some_and(|c| c.hp == 0)
+    }
+
+    /// Whether the NPC strikes at the player at the end of a turn.
+    pub fn is_hostile(&self) -> bool {
+        self.combat.as_ref().is_some_and(|c| {
+            c.hp > 0
+                && match c.behaviour {
+                    Behaviour::Aggressive => true,
+                    Behaviour::Defensive => c.provoked,
+                    Behaviour::Passive => false,
+                }
+        })
+    }
+}
+
+impl GameState {
+    /// The best defense among the carried items.
+    pub fn player_defense(&self) -> u32 {
+        self.player.inventory.ids().filter_map(|id| self.world.items.get(id)).map(|i| i.defense).max().unwrap_or(0)
+    }
+
+    /// The carried item with the highest attack, if any beats bare hands.
+    pub fn best_weapon(&self) -> Option<String> {
+        let mut weapons: Vec<_> = self
+            .player
+            .inventory
+            .ids()
+            .filter_map(|id| self.world.items.get(id))
+            .filter(|i| i.attack > FIST_ATTACK)
+            .collect();
+        weapons.sort_by(|a, b| b.attack.cmp(&a.attack).then(a.id.cmp(&b.id)));
+        weapons.first().map(|i| i.id.clone())
+    }
+
+    /// NPCs in the current node that will strike at the end of the turn.
+    pub fn hostiles_here(&self) -> Vec<&Npc> {
+        self.npcs_here().into_iter().filter(|n| n.is_hostile()).collect()
+    }
+
+    /// One round: the player strikes, and a surviving target strikes back at the
+    /// end of the turn along with every other hostile NPC here.
+    pub(crate) fn attack(&mut self, npc_id: String, weapon: Option<String>) {
+        let Some(npc) = self.npcs_here().into_iter().find(|n| n.id == npc_id).cloned() else {
+            return self.emit(GameEvent::NpcNotHere(npc_id));
+        };
+        let Some(stats) = npc.combat else {
+            return self.emit(GameEvent::CannotAttack(npc_id));
+        };
+        if let Some(item_id) = weapon.as_ref().filter(|id| !self.player.inventory.contains(id)) {
+            return self.emit(GameEvent::NotCarrying(item_id.clone()));
+        }
+
+        let weapon = weapon.or_else(|| self.best_weapon());
+        let attack = weapon.as_ref().and_then(|id| self.world.items.get(id)).map_or(FIST_ATTACK, |i| i.attack);
+        let damage = attack.saturating_add(self.rng.below(3)).saturating_sub(stats.defense).min(stats.hp);
+        let target_hp = stats.hp - damage;
+        if let Some(combat) = self.world.npcs.get_mut(&npc_id).and_then(|n| n.combat.as_mut()) {
+            combat.hp = target_hp;
+            combat.provoked = true;
+        }
+        self.emit(GameEvent::Attacked { npc_id: npc_id.clone(), weapon, damage, target_hp });
+        self.turn += 1;
+
+        if target_hp == 0 {
+            self.defeat(npc_id, stats.loot, stats.on_defeat);
+        }
+    }
+
+    fn defeat(&mut self, npc_id: String, loot: Vec<Loot>, on_defeat: Vec<Effect>) {
+        let mut dropped = ItemStacks::new();
+        for entry in &loot {
+            if self.rng.chance(entry.chance) {
+                dropped.add(&entry.item_id, entry.count);
+            }
+        }
+        let node_id = self.player.current_node.clone();
+        let result = self.atomically(|state| {
+            if let Some(node) = state.world.nodes.get_mut(&node_id) {
+                node.items.append(dropped.clone());
+            }
+            state.emit(GameEvent::EnemyDefeated { npc_id, loot: dropped });
+            state.apply_effects(&on_defeat)
+        });
+        if let Err(err) = result {
+            self.emit(GameEvent::EffectFailed(err));
+        }
+    }
+
+    /// Every hostile NPC here takes a swing at the player, until the player falls.
+    pub(crate) fn hostiles_strike(&mut self) {
+        let hostiles: Vec<Npc> = self.hostiles_here().into_iter().cloned().collect();
+        let defense = self.player_defense();
+        for npc in hostiles {
+            if self.player.hp == 0 {
+                break;
+            }
+            let attack = npc.combat.as_ref().map_or(0, |c| c.attack);
+            let damage = attack.saturating_add(self.rng.below(3)).saturating_sub(defense);
+            let from = self.adjust_hp(-i32::try_from(damage).unwrap_or(i32::MAX));
+            self.emit(GameEvent::EnemyAttacked { npc_id: npc.id, damage: from - self.player.hp, hp: self.player.hp });
+        }
+    }
+
+    /// Runs back the way the player came, or along any open path if that is blocked.
+    pub(crate) fn flee(&mut self) {
+        if self.hostiles_here().is_empty() {
+            return self.emit(GameEvent::NothingToFlee);
+        }
+        let edges = self.visible_edges();
+        let back = edges.iter().find(|e| Some(&e.target_id) == self.player.came_from.as_ref() && self.can_traverse(e));
+        let Some(edge) = back.or_else(|| edges.iter().find(|e| self.can_traverse(e))) else {
+            return self.emit(GameEvent::NoEscape);
+        };
+        let target_id = edge.target_id.clone();
+        self.emit(GameEvent::Fled(target_id.clone()));
+        self.move_to(target_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Effect, EffectError, GameAction, GameEvent, GameState, GameStatus, WorldTemplate};
+
+    const YAML: &str = r#"
+nodes:
+  start:
+    id: start
+    description: "A clearing."
+    attributes: {}
+    edges:
+      - target_id: den
+        label: "Enter the den"
+        conditions: []
+    items: [sword, shield]
+  den:
+    id: den
+    description: "A wolf den."
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Back to the clearing"
+        conditions: []
+    items: []
+items:
+  sword: { id: sword, name: "Sword", description: "Sharp.", can_pickup: true, attack: 6 }
+  shield: { id: shield, name: "Shield", description: "Sturdy.", can_pickup: true, defense: 2 }
+  pelt: { id: pelt, name: "Wolf Pelt", description: "Warm.", can_pickup: true }
+npcs:
+  wolf:
+    id: wolf
+    name: "Grey Wolf"
+    description: "A hungry wolf."
+    node_id: den
+    combat:
+      hp: 12
+      attack: 4
+      defense: 1
+      behaviour: Aggressive
+      loot:
+        - item_id: pelt
+      on_defeat:
+        - !SetFlag [wolf_slain, "true"]
+"#;
+
+    #[test]
+    fn test_combat_rounds() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        assert!(template.validate().is_empty());
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(GameAction::Pickup("sword".to_string(), None));
+        state.apply_action(GameAction::Pickup("shield".to_string(), None));
+        assert_eq!(state.parse_command("attack grey wolf with sword"),
+            GameAction::Attack("wolf".to_string(), Some("sword".to_string())));
+
+        // The wolf attacks on sight and the shield softens its bite
+        let events = state.apply_action(GameAction::Move("den".to_string()));
+        let Some(GameEvent::EnemyAttacked { damage, .. }) = events.last() else {
+            panic!("expected the wolf to attack: {:?}", events);
+        };
+        assert!((2..=4).contains(damage));
+
+        // The same state always plays out the same fight
+        let replay = state.clone().apply_action(state.parse_command("attack wolf"));
+        let events = state.apply_action(state.parse_command("attack wolf"));
+        assert_eq!(events, replay);
+        let GameEvent::Attacked { weapon, damage, target_hp, .. } = &events[0] else {
+            panic!("expected an attack: {:?}", events);
+        };
+        assert_eq!(weapon.as_deref(), Some("sword"));
+        assert!((5..=7).contains(damage));
+        assert_eq!(*target_hp, 12 - damage);
+        assert!(matches!(events[1], GameEvent::EnemyAttacked { .. }));
+
+        while state.world.npcs["wolf"].combat.as_ref().unwrap().hp > 0 {
+            state.apply_action(GameAction::Attack("wolf".to_string(), None));
+        }
+        assert_eq!(state.status, GameStatus::Playing);
+        assert!(state.world.nodes["den"].items.contains("pelt"));
+        assert_eq!(state.world.flags["wolf_slain"], "true");
+        assert!(state.npcs_here().is_empty());
+        assert_eq!(state.apply_action(GameAction::Flee), vec![GameEvent::NothingToFlee]);
+    }
+
+    #[test]
+    fn test_flee() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(GameAction::Move("den".to_string()));
+        let hp = state.player.hp;
+        let events = state.apply_action(state.parse_command("flee"));
+        assert_eq!(events[0], GameEvent::Fled("start".to_string()));
+        assert_eq!(state.player.current_node, "start");
+        assert_eq!(state.player.hp, hp);
+    }
+
+    #[test]
+    fn test_failed_defeat_and_huge_attacks() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        let wolf = state.world.npcs.get_mut("wolf").unwrap().combat.as_mut().unwrap();
+        wolf.hp = 1;
+        wolf.on_defeat.push(Effect::RemoveItem("pelt".to_string()));
+        state.apply_action(GameAction::Move("den".to_string()));
+
+        // A failing on_defeat effect takes the loot and the flag back with it
+        let events = state.apply_action(GameAction::Attack("wolf".to_string(), None));
+        assert_eq!(events.last(), Some(&GameEvent::EffectFailed(EffectError::MissingItem("pelt".to_string()))));
+        assert!(!events.iter().any(|e| matches!(e, GameEvent::EnemyDefeated { .. })));
+        assert!(state.world.npcs["wolf"].is_defeated());
+        assert!(!state.world.nodes["den"].items.contains("pelt"));
+        assert!(!state.world.flags.contains_key("wolf_slain"));
+
+        // An absurd attack kills instead of wrapping around into healing
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.world.npcs.get_mut("wolf").unwrap().combat.as_mut().unwrap().attack = u32::MAX;
+        state.apply_action(GameAction::Move("den".to_string()));
+        assert_eq!(state.player.hp, 0);
+        assert_eq!(state.status, GameStatus::Lost);
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{CombatStats, Condition, Effect, GameEvent, GameState};
+
+/// A character standing in a node who can be examined and talked to.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Npc {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    /// The node the NPC is currently in.
+    pub node_id: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    /// Dialogue nodes by id. `talk` starts at `start`.
+    #[serde(default)]
+    pub dialogue: HashMap<String, DialogueNode>,
+    /// Set for NPCs the player can fight.
+    #[serde(default)]
+    pub combat: Option<CombatStats>,
+}
+
+impl Npc {
+    pub fn is_called(&self, name: &str) -> bool {
+        let name = name.replace(' ', "_");
+        self.id.eq_ignore_ascii_case(&name) || self.name.replace(' ', "_").eq_ignore_ascii_case(&name)
+    }
+}
+
+/// Something the NPC says, followed by the answers the player can pick from.
+/// A line without choices ends the conversation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DialogueNode {
+    pub text: String,
+    #[serde(default)]
+    pub choices: Vec<DialogueChoice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DialogueChoice {
+    pub text: String,
+    /// The choice is only offered while these hold.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Applied when the player picks the choice, e.g. `!GiveItem` or `!RevealEdge`.
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// The dialogue node the NPC answers with; the conversation ends if not set.
+    #[serde(default)]
+    pub next: Option<String>,
+}
+
+/// Where the player is in a conversation.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Conversation {
+    pub npc_id: String,
+    pub node_id: String,
+}
+
+impl GameState {
+    /// NPCs in the current node that have not been defeated, ordered by id.
+    pub fn npcs_here(&self) -> Vec<&Npc> {
+        let mut npcs: Vec<&Npc> = self
+            .world
+            .npcs
+            .values()
+            .filter(|n| n.node_id == self.player.current_node && !n.is_defeated())
+            .collect();
+        npcs.sort_by(|a, b| a.id.cmp(&b.id));
+        npcs
+    }
+
+    /// The id of the NPC called `name`, preferring those nearby. Unknown names are
+    /// returned unchanged.
+    pub fn resolve_npc_name(&self, name: &str) -> String {
+        let mut npcs: Vec<&Npc> = self.world.npcs.values().collect();
+        npcs.sort_by_key(|n| (n.node_id != self.player.current_node, n.id.clone()));
+        npcs.into_iter().find(|n| n.is_called(name)).map_or(name.to_string(), |n| n.id.clone())
+    }
+
+    /// The choices on offer in the current conversation, in the order they are numbered.
+    pub fn dialogue_choices(&self) -> Vec<&DialogueChoice> {
+        let Some(conversation) = &self.player.conversation else {
+            return vec![];
+        };
+        self.world
+            .npcs
+            .get(&conversation.npc_id)
+            .and_then(|npc| npc.dialogue.get(&conversation.node_id))
+            .map(|node| node.choices.iter().filter(|c| self.check_conditions(&c.conditions)).collect())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn talk(&mut self, npc_id: String) {
+        let Some(npc) = self.npcs_here().into_iter().find(|n| n.id == npc_id) else {
+            return self.emit(GameEvent::NpcNotHere(npc_id));
+        };
+        if !npc.dialogue.contains_key("start") {
+            return self.emit(GameEvent::NothingToSay(npc_id));
+        }
+        self.show_dialogue(&npc_id, "start");
+        self.turn += 1;
+    }
+
+    /// Answers with the choice numbered `number`, counting from 1.
+    pub(crate) fn say(&mut self, number: usize) {
+        let Some(conversation) = self.player.conversation.clone() else {
+            return self.emit(GameEvent::NotTalking);
+        };
+        let choices = self.dialogue_choices();
+        let Some(choice) = number.checked_sub(1).and_then(|i| choices.get(i)).map(|c| (*c).clone()) else {
+            return self.emit(GameEvent::NoSuchChoice(number));
+        };
+
+        let result = self.atomically(|state| {
+            state.emit(GameEvent::ChoiceMade { npc_id: conversation.npc_id.clone(), text: choice.text.clone() });
+            state.apply_effects(&choice.effects)
+        });
+        if let Err(err) = result {
+            return self.emit(GameEvent::EffectFailed(err));
+        }
+        match &choice.next {
+            Some(node_id) => self.show_dialogue(&conversation.npc_id, node_id),
+            None => self.end_conversation(conversation.npc_id),
+        }
+        self.turn += 1;
+    }
+
+    fn show_dialogue(&mut self, npc_id: &str, node_id: &str) {
+        let Some(node) = self.world.npcs.get(npc_id).and_then(|n| n.dialogue.get(node_id)).cloned() else {
+            return self.end_conversation(npc_id.to_string());
+        };
+        self.player.conversation = Some(Conversation { npc_id: npc_id.to_string(), node_id: node_id.to_string() });
+        let choices: Vec<String> = self.dialogue_choices().iter().map(|c| c.text.clone()).collect();
+        let finished = choices.is_empty();
+        self.emit(GameEvent::DialogueLine { npc_id: npc_id.to_string(), text: node.text, choices });
+        if finished {
+            self.end_conversation(npc_id.to_string());
+        }
+    }
+
+    fn end_conversation(&mut self, npc_id: String) {
+        self.player.conversation = None;
+        self.emit(GameEvent::ConversationEnded(npc_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GameAction, GameEvent, GameState, WorldTemplate};
+
+    const YAML: &str = r#"
+nodes:
+  start:
+    id: start
+    description: "A market square."
+    attributes: {}
+    edges:
+      - target_id: alley
+        label: "Slip into the alley"
+        conditions: []
+        hidden: {}
+    items: []
+  alley:
+    id: alley
+    description: "A narrow alley."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  apple: { id: apple, name: "Apple", description: "Red.", can_pickup: true }
+npcs:
+  merchant:
+    id: merchant
+    name: "Old Merchant"
+    description: "A merchant with a sly smile."
+    node_id: start
+    dialogue:
+      start:
+        text: "What can I do for you?"
+        choices:
+          - text: "Give me an apple."
+            conditions:
+              - !Not
+                - !HasItem apple
+            effects:
+              - !GiveItem apple
+            next: start
+          - text: "Any secrets?"
+            next: secret
+          - text: "Goodbye."
+      secret:
+        text: "There is an alley behind my stall."
+        choices:
+          - text: "Show me."
+            effects:
+              - !RevealEdge [start, alley]
+              - !SetNpcAttribute [merchant, trusts_you, "true"]
+"#;
+
+    #[test]
+    fn test_dialogue_tree() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        assert!(!template.validate().iter().any(|d| d.is_error()));
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(state.parse_command("explore old merchant"));
+        assert_eq!(state.log.last(), Some(&"A merchant with a sly smile.".to_string()));
+
+        let events = state.apply_action(state.parse_command("talk to old merchant"));
+        assert_eq!(events, vec![GameEvent::DialogueLine {
+            npc_id: "merchant".to_string(),
+            text: "What can I do for you?".to_string(),
+            choices: vec!["Give me an apple.".to_string(), "Any secrets?".to_string(), "Goodbye.".to_string()],
+        }]);
+        assert_eq!(state.log.last(), Some(&"  3. Goodbye.".to_string()));
+
+        // Gated choices disappear and the numbering closes up
+        state.apply_action(state.parse_command("1"));
+        assert!(state.player.inventory.contains("apple"));
+        assert_eq!(state.dialogue_choices().len(), 2);
+        assert_eq!(state.parse_command("say 1"), GameAction::Say(1));
+        let events = state.apply_action(GameAction::Say(3));
+        assert_eq!(events, vec![GameEvent::NoSuchChoice(3)]);
+
+        state.apply_action(GameAction::Say(1));
+        let events = state.apply_action(GameAction::Say(1));
+        assert!(events.contains(&GameEvent::NpcAttributeChanged {
+            npc_id: "merchant".to_string(),
+            key: "trusts_you".to_string(),
+            value: "true".to_string(),
+        }));
+        assert!(state.player.conversation.is_none());
+        assert_eq!(state.world.npcs["merchant"].attributes["trusts_you"], "true");
+        state.apply_action(GameAction::Move("alley".to_string()));
+        assert_eq!(state.player.current_node, "alley");
+
+        let events = state.apply_action(GameAction::Talk("merchant".to_string()));
+        assert_eq!(events, vec![GameEvent::NpcNotHere("merchant".to_string())]);
+    }
+}
@@ -0,0 +1,249 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Condition, GameStatus, ItemStacks};
+
+/// Machine-readable outcome of an action. `GameState::apply_action` returns the
+/// events of one action; the text log is rendered from them by an `EventRenderer`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GameEvent {
+    HelpShown,
+    Looked {
+        node_id: String,
+        description: String,
+        items: ItemStacks,
+        npcs: Vec<String>,
+        paths: Vec<String>,
+    },
+    Moved {
+        from: String,
+        to: String,
+        label: String,
+    },
+    MoveBlocked {
+        target_id: String,
+        label: String,
+        unmet: Vec<Condition>,
+    },
+    NoPath(String),
+    ItemExamined(String),
+    ItemNotVisible(String),
+    FeatureExamined {
+        feature_id: String,
+        description: String,
+    },
+    NothingSpecial(String),
+    NothingFound,
+    EdgeDiscovered {
+        node_id: String,
+        target_id: String,
+        label: String,
+        message: Option<String>,
+    },
+    ItemDiscovered {
+        node_id: String,
+        item_id: String,
+        message: Option<String>,
+    },
+    ItemPickedUp(String, u32), // item_id, count
+    CannotPickUp(String),
+    TooHeavy {
+        item_id: String,
+        carried: u32,
+        max: u32,
+    },
+    InventoryFull {
+        item_id: String,
+        max_slots: u32,
+    },
+    ItemNotHere(String),
+    ItemDropped(String, u32), // item_id, count
+    NotCarrying(String),
+    NotEnough {
+        item_id: String,
+        available: u32,
+    },
+    InventoryShown(ItemStacks),
+    ContainerOpened {
+        container_id: String,
+        contents: ItemStacks,
+    },
+    ContainerSearched {
+        container_id: String,
+        contents: ItemStacks,
+    },
+    ContainerClosed(String),
+    ContainerLocked(String),
+    ContainerUnlocked(String),
+    ContainerIsClosed(String),
+    AlreadyOpen(String),
+    AlreadyClosed(String),
+    NotAContainer(String),
+    ItemPutIn {
+        item_id: String,
+        container_id: String,
+        count: u32,
+    },
+    CannotPutInside {
+        item_id: String,
+        container_id: String,
+    },
+    ItemTakenFrom {
+        item_id: String,
+        container_id: String,
+        count: u32,
+    },
+    NotInContainer {
+        item_id: String,
+        container_id: String,
+    },
+    MapShown(String),
+    Combined {
+        combination: usize, // index into World::combinations
+        ingredients: ItemStacks,
+        results: ItemStacks,
+        message: Option<String>,
+    },
+    CannotCombine(Vec<String>),
+    MissingIngredients(Vec<String>),
+    CombineBlocked(Vec<String>),
+    ItemUsed {
+        item_id: String,
+        target: Option<String>,
+        message: Option<String>,
+    },
+    FeatureUsed {
+        feature_id: String,
+        message: Option<String>,
+    },
+    UseBlocked {
+        item_id: String,
+        target: Option<String>,
+    },
+    NothingHappens {
+        item_id: String,
+        target: Option<String>,
+    },
+    Message(String),
+    AttributeChanged {
+        key: String,
+        value: Option<String>,
+    },
+    HpChanged {
+        from: u32,
+        to: u32,
+    },
+    HazardHit {
+        damage: u32,
+        message: Option<String>,
+    },
+    ItemConsumed {
+        item_id: String,
+        healed: u32,
+    },
+    NoNeedToConsume(String),
+    StatusAdded(String),
+    StatusRemoved(String),
+    StatusTicked {
+        status_id: String,
+        from: u32,
+        to: u32,
+    },
+    CheckpointReached(String),
+    NpcExamined(String),
+    NpcNotHere(String),
+    NothingToSay(String),
+    DialogueLine {
+        npc_id: String,
+        text: String,
+        choices: Vec<String>,
+    },
+    ChoiceMade {
+        npc_id: String,
+        text: String,
+    },
+    NoSuchChoice(usize),
+    NotTalking,
+    ConversationEnded(String),
+    /// An outcome of a random effect was picked; `table_id` is None for inline `Random` effects.
+    Rolled {
+        table_id: Option<String>,
+        outcome: usize,
+    },
+    /// One blow by the player; `damage` is 0 for a blow that glances off.
+    Attacked {
+        npc_id: String,
+        weapon: Option<String>,
+        damage: u32,
+        target_hp: u32,
+    },
+    EnemyAttacked {
+        npc_id: String,
+        damage: u32,
+        hp: u32,
+    },
+    EnemyDefeated {
+        npc_id: String,
+        loot: ItemStacks,
+    },
+    CannotAttack(String),
+    Fled(String),
+    NothingToFlee,
+    NoEscape,
+    Respawned(String),
+    ItemReceived(String),
+    ItemRemoved(String),
+    FlagChanged {
+        key: String,
+        value: String,
+    },
+    ItemStateChanged {
+        item_id: String,
+        key: String,
+        value: String,
+    },
+    ItemSpawned {
+        item_id: String,
+        node_id: String,
+    },
+    EdgeAdded {
+        node_id: String,
+        target_id: String,
+    },
+    EdgeRemoved {
+        node_id: String,
+        target_id: String,
+    },
+    DescriptionChanged(String), // node_id
+    NpcMoved {
+        npc_id: String,
+        node_id: String,
+    },
+    NpcAttributeChanged {
+        npc_id: String,
+        key: String,
+        value: String,
+    },
+    Teleported(String),
+    EffectFailed(EffectError),
+    GameEnded {
+        status: GameStatus,
+        message: String,
+    },
+    GameIsOver,
+    Undone(usize),
+    NothingToUndo,
+    UnknownCommand(String),
+}
+
+/// Why a list of effects could not be applied.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum EffectError {
+    MissingItem(String),
+    UnknownNode(String),
+    UnknownItem(String),
+    UnknownNpc(String),
+    UnknownTable(String),
+    NoSuchEdge(String, String), // node_id, target_id
+    /// Teleports kept leading to more teleports, the last one to this node.
+    TeleportLoop(String),
+}
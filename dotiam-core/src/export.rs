@@ -0,0 +1,237 @@
+use crate::{Combination, Condition, Edge, ItemStack, Node, WorldTemplate};
+
+fn sorted_nodes(template: &WorldTemplate) -> Vec<&Node> {
+    let mut nodes: Vec<&Node> = template.nodes.values().collect();
+    nodes.sort_by(|a, b| a.id.cmp(&b.id));
+    nodes
+}
+
+/// The node id followed by its items, features and hidden items.
+fn node_lines(node: &Node) -> Vec<String> {
+    let mut lines = vec![node.id.clone()];
+    if !node.items.is_empty() {
+        let items: Vec<String> = node.items.iter().map(ItemStack::to_string).collect();
+        lines.push(format!("items: {}", items.join(", ")));
+    }
+    if !node.features.is_empty() {
+        let mut features: Vec<&str> = node.features.keys().map(String::as_str).collect();
+        features.sort();
+        lines.push(format!("features: {}", features.join(", ")));
+    }
+    if !node.hidden_items.is_empty() {
+        let hidden: Vec<&str> = node.hidden_items.iter().map(|h| h.item_id.as_str()).collect();
+        lines.push(format!("hidden: {}", hidden.join(", ")));
+    }
+    lines
+}
+
+/// The edge label followed by its conditions in brackets.
+fn edge_lines(edge: &Edge) -> Vec<String> {
+    let mut lines = vec![if edge.hidden.is_some() { format!("{} (hidden)", edge.label) } else { edge.label.clone() }];
+    if !edge.conditions.is_empty() {
+        let conditions: Vec<String> = edge.conditions.iter().map(Condition::to_string).collect();
+        lines.push(format!("[{}]", conditions.join(" and ")));
+    }
+    lines
+}
+
+/// Item ids taking part in any combination, sorted and without duplicates.
+fn crafting_items(combinations: &[Combination]) -> Vec<&String> {
+    let mut item_ids: Vec<&String> = combinations
+        .iter()
+        .flat_map(|c| c.ingredients.iter().chain(&c.results).map(|s| &s.item_id).chain(&c.tools))
+        .collect();
+    item_ids.sort();
+    item_ids.dedup();
+    item_ids
+}
+
+/// The recipe node label, with its conditions in brackets.
+fn recipe_lines(combination: &Combination) -> Vec<String> {
+    let mut lines = vec!["+".to_string()];
+    if !combination.conditions.is_empty() {
+        let conditions: Vec<String> = combination.conditions.iter().map(Condition::to_string).collect();
+        lines.push(format!("[{}]", conditions.join(" and ")));
+    }
+    lines
+}
+
+/// Ingredients point into the recipe (`true`), results out of it.
+fn recipe_edges(combination: &Combination) -> Vec<(bool, &ItemStack)> {
+    let ingredients = combination.ingredients.iter().map(|s| (true, s));
+    let results = combination.results.iter().map(|s| (false, s));
+    ingredients.chain(results).collect()
+}
+
+fn dot_string<S: AsRef<str>>(lines: &[S]) -> String {
+    let escaped: Vec<String> = lines.iter().map(|l| l.as_ref().replace('\\', "\\\\").replace('"', "\\\"")).collect();
+    format!("\"{}\"", escaped.join("\\n"))
+}
+
+/// Mermaid ids may only use letters, digits and `_`, so `_` is written `__` and
+/// every other byte `_xx` in hex. Distinct ids thus never share a Mermaid id.
+fn mermaid_id(prefix: &str, id: &str) -> String {
+    let mut escaped = String::new();
+    for byte in id.bytes() {
+        match byte {
+            b'_' => escaped.push_str("__"),
+            b if b.is_ascii_alphanumeric() => escaped.push(b as char),
+            b => escaped.push_str(&format!("_{:02x}", b)),
+        }
+    }
+    format!("{}_{}", prefix, escaped)
+}
+
+fn mermaid_string<S: AsRef<str>>(lines: &[S]) -> String {
+    let escaped: Vec<String> = lines.iter().map(|l| l.as_ref().replace('"', "#quot;")).collect();
+    format!("\"{}\"", escaped.join("<br/>"))
+}
+
+impl WorldTemplate {
+    /// Graphviz source for the world graph. Conditional edges are dashed and the
+    /// combinations form a separate "Crafting" cluster.
+    pub fn to_dot(&self) -> String {
+        let mut out = vec![
+            "digraph world {".to_string(),
+            "    rankdir=LR;".to_string(),
+            "    node [shape=box];".to_string(),
+        ];
+        for node in sorted_nodes(self) {
+            out.push(format!("    {} [label={}];", dot_string(&[node.id.as_str()]), dot_string(&node_lines(node))));
+        }
+        for node in sorted_nodes(self) {
+            for edge in &node.edges {
+                let style = if edge.conditions.is_empty() { "" } else { ", style=dashed" };
+                out.push(format!(
+                    "    {} -> {} [label={}{}];",
+                    dot_string(&[node.id.as_str()]),
+                    dot_string(&[edge.target_id.as_str()]),
+                    dot_string(&edge_lines(edge)),
+                    style
+                ));
+            }
+        }
+
+        if !self.combinations.is_empty() {
+            out.push("    subgraph cluster_crafting {".to_string());
+            out.push("        label=\"Crafting\";".to_string());
+            out.push("        node [shape=ellipse];".to_string());
+            for (i, combination) in self.combinations.iter().enumerate() {
+                let recipe = dot_string(&[format!("combination:{}", i)]);
+                out.push(format!("        {} [label={}, shape=circle];", recipe, dot_string(&recipe_lines(combination))));
+                for (incoming, stack) in recipe_edges(combination) {
+                    let item = dot_string(&[format!("item:{}", stack.item_id)]);
+                    let label = if stack.count > 1 { format!(" [label=\"{}\"]", stack.count) } else { String::new() };
+                    let (from, to) = if incoming { (&item, &recipe) } else { (&recipe, &item) };
+                    out.push(format!("        {} -> {}{};", from, to, label));
+                }
+                for tool in &combination.tools {
+                    let item = dot_string(&[format!("item:{}", tool)]);
+                    out.push(format!("        {} -> {} [label=\"tool\", style=dashed];", item, recipe));
+                }
+            }
+            for item_id in crafting_items(&self.combinations) {
+                out.push(format!("        {} [label={}];", dot_string(&[format!("item:{}", item_id)]), dot_string(&[item_id.as_str()])));
+            }
+            out.push("    }".to_string());
+        }
+        out.push("}".to_string());
+        out.join("\n") + "\n"
+    }
+
+    /// Mermaid flowchart of the world graph, with the combinations in a
+    /// "Crafting" subgraph. Node ids are prefixed so that ids like `end` are safe.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = vec!["flowchart LR".to_string()];
+        for node in sorted_nodes(self) {
+            out.push(format!("    {}[{}]", mermaid_id("node", &node.id), mermaid_string(&node_lines(node))));
+        }
+        for node in sorted_nodes(self) {
+            for edge in &node.edges {
+                let arrow = if edge.conditions.is_empty() { "-->" } else { "-.->" };
+                out.push(format!(
+                    "    {} {}|{}| {}",
+                    mermaid_id("node", &node.id),
+                    arrow,
+                    mermaid_string(&edge_lines(edge)),
+                    mermaid_id("node", &edge.target_id)
+                ));
+            }
+        }
+
+        if !self.combinations.is_empty() {
+            out.push("    subgraph crafting [Crafting]".to_string());
+            for item_id in crafting_items(&self.combinations) {
+                out.push(format!("        {}([{}])", mermaid_id("item", item_id), mermaid_string(&[item_id.as_str()])));
+            }
+            for (i, combination) in self.combinations.iter().enumerate() {
+                let recipe = format!("combination_{}", i);
+                out.push(format!("        {}(({}))", recipe, mermaid_string(&recipe_lines(combination))));
+                for (incoming, stack) in recipe_edges(combination) {
+                    let item = mermaid_id("item", &stack.item_id);
+                    let arrow = if stack.count > 1 { format!("-->|{}|", stack.count) } else { "-->".to_string() };
+                    let (from, to) = if incoming { (&item, &recipe) } else { (&recipe, &item) };
+                    out.push(format!("        {} {} {}", from, arrow, to));
+                }
+                for tool in &combination.tools {
+                    out.push(format!("        {} -.->|tool| {}", mermaid_id("item", tool), recipe));
+                }
+            }
+            out.push("    end".to_string());
+        }
+        out.join("\n") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mermaid_id;
+    use crate::WorldTemplate;
+
+    fn whispering_woods() -> WorldTemplate {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml")
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = whispering_woods().to_dot();
+        assert!(dot.starts_with("digraph world {"));
+        assert!(dot.contains("\"start\" -> \"bridge\" [label=\"Cross the Stone Bridge (North)\"];"));
+        assert!(dot.contains(
+            "\"hut_exterior\" -> \"hut_interior\" [label=\"Enter the Hut\\n[flag hut_unlocked = true]\", style=dashed];"
+        ));
+        assert!(dot.contains("subgraph cluster_crafting {"));
+        assert!(dot.contains("\"item:flint\" -> \"combination:0\";"));
+        assert!(dot.contains("\"combination:0\" -> \"item:torch\";"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mermaid = whispering_woods().to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("node_start -->|\"Cross the Stone Bridge (North)\"| node_bridge"));
+        assert!(mermaid.contains("node_hut__exterior -.->|\"Enter the Hut<br/>[flag hut_unlocked = true]\"| node_hut__interior"));
+        assert!(mermaid.contains("    subgraph crafting [Crafting]\n"));
+        assert!(mermaid.contains("combination_0 --> item_torch"));
+    }
+
+    #[test]
+    fn test_mermaid_ids_do_not_collide() {
+        assert_eq!(mermaid_id("node", "a-b"), "node_a_2db");
+        assert_eq!(mermaid_id("node", "a_b"), "node_a__b");
+        assert_eq!(mermaid_id("node", "a_2db"), "node_a__2db");
+
+        let mut template = whispering_woods();
+        let mut dashed = template.nodes["bridge"].clone();
+        dashed.id = "stone-bridge".to_string();
+        template.nodes.insert(dashed.id.clone(), dashed);
+        let mut underscored = template.nodes["bridge"].clone();
+        underscored.id = "stone_bridge".to_string();
+        template.nodes.insert(underscored.id.clone(), underscored);
+        let mermaid = template.to_mermaid();
+        assert!(mermaid.contains("    node_stone_2dbridge["));
+        assert!(mermaid.contains("    node_stone__bridge["));
+    }
+}
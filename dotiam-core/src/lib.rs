@@ -1,5 +1,30 @@
+use serde::de::value::{EnumAccessDeserializer, MapAccessDeserializer};
+use serde::de::{Deserializer, EnumAccess, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+mod analysis;
+mod combat;
+mod dialogue;
+mod events;
+mod export;
+mod map;
+mod random;
+mod render;
+mod solver;
+mod stacks;
+mod validate;
+
+pub use analysis::{AnalysisOptions, AnalysisReport, Problem, ProblemKind, analyze};
+pub use combat::{Behaviour, CombatStats, Loot};
+pub use dialogue::{Conversation, DialogueChoice, DialogueNode, Npc};
+pub use events::{EffectError, GameEvent};
+pub use random::{Outcome, Rng};
+pub use render::{EnglishRenderer, EventRenderer};
+pub use solver::{Goal, Solution, solve};
+pub use stacks::{ItemStack, ItemStacks};
+pub use validate::{Diagnostic, DiagnosticKind, Severity};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(transparent)]
@@ -12,6 +37,152 @@ pub enum ConditionType {
     HasItem(String),
     HasAttribute(String, String),
     MinHP(u32),
+    MaxHP(u32),
+    /// Numeric comparison of a player attribute, e.g. `!AttributeCompare [strength, ">=", 3]`.
+    /// Attributes that are missing or not numbers never match.
+    AttributeCompare(String, Comparison, i64),
+    TurnAtLeast(u32),
+    TurnBefore(u32),
+    HasVisited(String),
+    AtNode(String),
+    ItemAt(String, String), // item_id, node_id
+    NodeAttribute(String, String, String), // node_id, key, value
+    WorldFlag(String, String),
+    HasSeen(String), // item_id
+    EdgeDiscovered(String, String), // node_id, target_id
+    /// Compares the total weight in the inventory, e.g. `!CarriedWeight ["<=", 10]`.
+    CarriedWeight(Comparison, i64),
+    /// A key of `Item::state`, e.g. `!ItemState [torch, lit, "true"]`. Unset keys never match.
+    ItemState(String, String, String), // item_id, key, value
+    HasStatus(String), // status id
+    NpcAttribute(String, String, String), // npc_id, key, value
+    NpcDefeated(String), // npc_id
+    /// True when every nested condition holds.
+    All(Vec<Condition>),
+    /// True when at least one nested condition holds.
+    Any(Vec<Condition>),
+    /// True when the wrapped condition does not hold, written `!Not [!HasItem x]`.
+    /// serde_yaml 0.9 cannot read or write a tag directly inside another tag ("nested
+    /// enum ... not supported yet"), so the condition sits in a one-element list.
+    /// Deserializing rejects any other length, and `WorldTemplate::validate` reports
+    /// one built in code.
+    Not(#[serde(deserialize_with = "single_condition")] Vec<Condition>),
+}
+
+/// Reads the list of `ConditionType::Not`, which must hold exactly one condition.
+fn single_condition<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Condition>, D::Error> {
+    let conditions = Vec::<Condition>::deserialize(deserializer)?;
+    if conditions.len() != 1 {
+        return Err(serde::de::Error::invalid_length(conditions.len(), &"exactly one condition"));
+    }
+    Ok(conditions)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Comparison {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+impl Comparison {
+    pub fn compare(&self, left: i64, right: i64) -> bool {
+        match self {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+fn join_conditions(conditions: &[Condition], separator: &str) -> String {
+    conditions.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(separator)
+}
+
+/// Short human-readable form used by the graph exporters, e.g. `has iron_key`.
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.condition_type {
+            ConditionType::HasItem(item_id) => write!(f, "has {}", item_id),
+            ConditionType::HasAttribute(key, value) => write!(f, "{} = {}", key, value),
+            ConditionType::MinHP(hp) => write!(f, "hp >= {}", hp),
+            ConditionType::MaxHP(hp) => write!(f, "hp <= {}", hp),
+            ConditionType::AttributeCompare(key, comparison, value) => write!(f, "{} {} {}", key, comparison, value),
+            ConditionType::TurnAtLeast(turn) => write!(f, "turn >= {}", turn),
+            ConditionType::TurnBefore(turn) => write!(f, "turn < {}", turn),
+            ConditionType::HasVisited(node_id) => write!(f, "visited {}", node_id),
+            ConditionType::AtNode(node_id) => write!(f, "at {}", node_id),
+            ConditionType::ItemAt(item_id, node_id) => write!(f, "{} at {}", item_id, node_id),
+            ConditionType::NodeAttribute(node_id, key, value) => write!(f, "{}.{} = {}", node_id, key, value),
+            ConditionType::WorldFlag(key, value) => write!(f, "flag {} = {}", key, value),
+            ConditionType::HasSeen(item_id) => write!(f, "seen {}", item_id),
+            ConditionType::EdgeDiscovered(node_id, target_id) => write!(f, "found {} -> {}", node_id, target_id),
+            ConditionType::CarriedWeight(comparison, value) => write!(f, "carried weight {} {}", comparison, value),
+            ConditionType::ItemState(item_id, key, value) => write!(f, "{}.{} = {}", item_id, key, value),
+            ConditionType::HasStatus(status_id) => write!(f, "status {}", status_id),
+            ConditionType::NpcAttribute(npc_id, key, value) => write!(f, "{}.{} = {}", npc_id, key, value),
+            ConditionType::NpcDefeated(npc_id) => write!(f, "defeated {}", npc_id),
+            ConditionType::All(conditions) => write!(f, "({})", join_conditions(conditions, " and ")),
+            ConditionType::Any(conditions) => write!(f, "({})", join_conditions(conditions, " or ")),
+            ConditionType::Not(conditions) if conditions.len() == 1 => write!(f, "not {}", conditions[0]),
+            ConditionType::Not(conditions) => write!(f, "not ({})", join_conditions(conditions, " and ")),
+        }
+    }
+}
+
+/// A change to the player or the world, declared in world.yaml and applied by `GameState::apply_effects`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Effect {
+    SetAttribute(String, String),
+    UnsetAttribute(String),
+    AdjustHP(i32),
+    GiveItem(String),
+    RemoveItem(String),
+    SpawnItem(String, String), // item_id, node_id
+    AddEdge(String, Edge), // node_id, edge
+    RevealEdge(String, String), // node_id, target_id
+    RemoveEdge(String, String), // node_id, target_id
+    SetDescription(String, String), // node_id, description
+    SetFlag(String, String),
+    SetItemState(String, String, String), // item_id, key, value
+    /// Replaces any status with the same id, e.g. `!AddStatus { id: poison, hp_per_turn: -2, turns: 3 }`.
+    AddStatus(StatusEffect),
+    RemoveStatus(String), // status id
+    SetNpcAttribute(String, String, String), // npc_id, key, value
+    MoveNpc(String, String), // npc_id, node_id
+    /// Applies one of the outcomes, picked by weight with the run's seeded generator.
+    Random(Vec<Outcome>),
+    /// Like `Random`, with the outcomes of a table in `World::tables`.
+    RollTable(String), // table_id
+    Message(String),
+    Teleport(String), // node_id
+    EndGame(GameStatus, String),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -19,6 +190,105 @@ pub struct Edge {
     pub target_id: String,
     pub label: String,
     pub conditions: Vec<Condition>,
+    /// Applied after the player traverses the edge.
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// Not listed or usable until the player discovers it with `explore` or a `RevealEdge` effect.
+    #[serde(default)]
+    pub hidden: Option<Discovery>,
+    /// Hurts the player each time the edge is taken.
+    #[serde(default)]
+    pub hazard: Option<Hazard>,
+}
+
+/// Damage dealt by a dangerous path or place, e.g. `{ damage: 10, conditions: [!Not [!HasItem boots]] }`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Hazard {
+    pub damage: u32,
+    /// Only hurts while these hold, so protective gear can be modelled with `!Not`.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// A lasting effect on the player such as poison or regeneration, applied at the
+/// end of every turn that passes.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StatusEffect {
+    pub id: String,
+    /// Negative for damage over time.
+    #[serde(default)]
+    pub hp_per_turn: i32,
+    /// Turns left before it wears off; lasts until removed if not set.
+    #[serde(default)]
+    pub turns: Option<u32>,
+}
+
+/// How `explore` finds a hidden edge.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Discovery {
+    /// Only `explore <feature>` finds it; otherwise exploring the room does.
+    #[serde(default)]
+    pub feature: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Shown instead of the default text when it is found.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// An item in a node that stays out of sight until `explore` reveals it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HiddenItem {
+    pub item_id: String,
+    /// Only `explore <feature>` finds it; otherwise exploring the room does.
+    #[serde(default)]
+    pub feature: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Shown instead of the default text when it is found.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// A named part of the scenery, such as a door or a fireplace. Features can be
+/// explored and used, but never picked up.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Feature {
+    pub id: String,
+    /// Other names the player may use, e.g. `book` for `recipe_book`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub description: String,
+    /// The feature is only there while these hold.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub on_use: Vec<FeatureUse>,
+}
+
+impl Feature {
+    pub fn is_called(&self, name: &str) -> bool {
+        self.id.eq_ignore_ascii_case(name) || self.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+    }
+}
+
+/// One way to use a feature. Use rules of the item take precedence over these.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeatureUse {
+    /// The item used on the feature; `None` matches `use <feature>` on its own.
+    #[serde(default)]
+    pub item: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// Removes the used item from the inventory.
+    #[serde(default)]
+    pub consume: bool,
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -27,13 +297,208 @@ pub struct Item {
     pub name: String,
     pub description: String,
     pub can_pickup: bool,
+    #[serde(default)]
+    pub on_pickup: Vec<Effect>,
+    #[serde(default)]
+    pub use_rules: Vec<UseRule>,
+    /// Makes the item hold other items, like a chest or a bag; every unit starts out like this.
+    #[serde(default)]
+    pub container: Option<Container>,
+    /// Weight of one unit, counted against `Player::max_carry_weight`.
+    #[serde(default)]
+    pub weight: u32,
+    /// Inventory slots a stack of this item takes up; 0 for things that fit anywhere.
+    #[serde(default = "default_bulk")]
+    pub bulk: u32,
+    /// What is true of every unit to begin with, e.g. `lit: "true"` or `charges: "3"`.
+    /// `SetItemState` changes one unit, which keeps the difference in its `ItemStack`.
+    #[serde(default)]
+    pub state: HashMap<String, String>,
+    /// HP restored when the item is used on its own and used up, like a potion.
+    #[serde(default)]
+    pub heals: u32,
+    /// Status ids removed when the item is used up, e.g. `[poison]` for an antidote.
+    #[serde(default)]
+    pub cures: Vec<String>,
+    /// Damage dealt when fighting with the item; the best carried weapon is used by default.
+    #[serde(default)]
+    pub attack: u32,
+    /// Damage soaked up while the item is carried, like armor or a shield.
+    #[serde(default)]
+    pub defense: u32,
+}
+
+fn default_bulk() -> u32 {
+    1
+}
+
+impl Item {
+    /// Items eaten or drunk by `use <item>` when no use rule applies.
+    pub fn is_consumable(&self) -> bool {
+        self.heals > 0 || !self.cures.is_empty()
+    }
+
+    /// The state of the units in `stack` for display, e.g. `lit, charges 3`. Keys
+    /// set to `false` are left out.
+    pub fn state_label(&self, stack: &ItemStack) -> Option<String> {
+        let mut state: BTreeMap<&String, &String> = self.state.iter().collect();
+        state.extend(&stack.state);
+        let parts: Vec<String> = state
+            .into_iter()
+            .filter_map(|(key, value)| match value.as_str() {
+                "true" => Some(key.clone()),
+                "false" => None,
+                value => Some(format!("{} {}", key, value)),
+            })
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+/// How a container item starts out. Once a unit is opened, filled or emptied it
+/// keeps its own copy in its `ItemStack`, so every bag has contents of its own
+/// and a carried bag keeps them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Container {
+    /// Items inside, which may be containers themselves.
+    #[serde(default)]
+    pub contents: ItemStacks,
+    #[serde(default)]
+    pub open: bool,
+    #[serde(default)]
+    pub locked: bool,
+    /// Unlock a locked container when the player opens it, e.g. `!HasItem chest_key`.
+    #[serde(default)]
+    pub unlock_conditions: Vec<Condition>,
+}
+
+/// Where a stack lies: in the inventory or the current node, then through the
+/// contents of the containers it is nested in, as stack indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StackPath {
+    in_inventory: bool,
+    indexes: Vec<usize>,
+}
+
+/// The unit's own container, copied from its item's definition the first time.
+fn own_container<'a>(items: &HashMap<String, Item>, unit: &'a mut ItemStack) -> Option<&'a mut Container> {
+    if unit.container.is_none() {
+        unit.container = items.get(&unit.item_id)?.container.clone();
+    }
+    unit.container.as_mut()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UseTarget {
+    /// Another item, either carried or lying in the current node.
+    Item(String),
+    /// A named part of the scenery, e.g. `door` in `use iron_key on door`.
+    Feature(String),
+    /// The node itself; matches `use <item>` without a target while standing there.
+    Node(String),
+}
+
+/// One way an item can be used. The first rule whose target matches and whose
+/// conditions hold is applied.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UseRule {
+    #[serde(default)]
+    pub target: Option<UseTarget>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+    /// Removes the used item from the inventory.
+    #[serde(default)]
+    pub consume: bool,
+    #[serde(default)]
+    pub message: Option<String>,
 }
 
+/// A crafting recipe. Items are plain ids or `{ item_id: arrow, count: 3 }` when
+/// several units are consumed or produced. The two-item form `item1`/`item2`/`result`
+/// is still read and written for recipes that fit it.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(from = "CombinationEntry", into = "CombinationEntry")]
 pub struct Combination {
-    pub item1: String,
-    pub item2: String,
-    pub result: String,
+    /// Used up by crafting; they must be carried.
+    pub ingredients: Vec<ItemStack>,
+    /// Needed but kept, like a cauldron. Tools lying in the current node count too.
+    pub tools: Vec<String>,
+    pub results: Vec<ItemStack>,
+    /// Extra requirements such as the location or a player attribute.
+    pub conditions: Vec<Condition>,
+    pub message: Option<String>,
+}
+
+impl Combination {
+    /// Whether `combine` with these item ids asks for this recipe: every ingredient
+    /// is named and nothing else but its tools. Tools may be left out.
+    pub fn is_named_by(&self, item_ids: &[String]) -> bool {
+        self.ingredients.iter().all(|s| item_ids.contains(&s.item_id))
+            && item_ids.iter().all(|id| self.ingredients.iter().any(|s| s.item_id == *id) || self.tools.contains(id))
+    }
+
+    /// Ingredient ids followed by tool ids.
+    pub fn item_ids(&self) -> Vec<String> {
+        self.ingredients.iter().map(|s| s.item_id.clone()).chain(self.tools.iter().cloned()).collect()
+    }
+}
+
+/// How a combination is written in YAML: either form, or both mixed when a
+/// two-item recipe gets extra ingredients.
+#[derive(Serialize, Deserialize)]
+struct CombinationEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    item1: Option<ItemStack>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    item2: Option<ItemStack>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<ItemStack>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    ingredients: Vec<ItemStack>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    results: Vec<ItemStack>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    conditions: Vec<Condition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl From<CombinationEntry> for Combination {
+    fn from(entry: CombinationEntry) -> Self {
+        Self {
+            ingredients: entry.item1.into_iter().chain(entry.item2).chain(entry.ingredients).collect(),
+            tools: entry.tools,
+            results: entry.result.into_iter().chain(entry.results).collect(),
+            conditions: entry.conditions,
+            message: entry.message,
+        }
+    }
+}
+
+impl From<Combination> for CombinationEntry {
+    fn from(combination: Combination) -> Self {
+        let mut entry = CombinationEntry {
+            item1: None,
+            item2: None,
+            result: None,
+            ingredients: combination.ingredients,
+            tools: combination.tools,
+            results: combination.results,
+            conditions: combination.conditions,
+            message: combination.message,
+        };
+        if entry.ingredients.len() == 2 && entry.results.len() == 1 {
+            let mut ingredients = std::mem::take(&mut entry.ingredients).into_iter();
+            entry.item1 = ingredients.next();
+            entry.item2 = ingredients.next();
+            entry.result = entry.results.pop();
+        }
+        entry
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -42,7 +507,82 @@ pub struct Node {
     pub description: String,
     pub attributes: HashMap<String, String>,
     pub edges: Vec<Edge>,
-    pub items: Vec<String>,
+    pub items: ItemStacks,
+    #[serde(default)]
+    pub hidden_items: Vec<HiddenItem>,
+    #[serde(default)]
+    pub features: HashMap<String, Feature>,
+    #[serde(default)]
+    pub first_visit_description: Option<String>,
+    /// Extra sentences appended to the description while their conditions hold.
+    #[serde(default)]
+    pub description_fragments: Vec<DescriptionFragment>,
+    #[serde(default)]
+    pub on_enter: Vec<Trigger>,
+    #[serde(default)]
+    pub on_first_enter: Vec<Trigger>,
+    #[serde(default)]
+    pub on_exit: Vec<Trigger>,
+    /// Fixed map coordinates (x grows east, y grows south); otherwise the map
+    /// places the node from compass hints in edge labels.
+    #[serde(default)]
+    pub position: Option<(i32, i32)>,
+    /// Hurts the player on every entry.
+    #[serde(default)]
+    pub hazard: Option<Hazard>,
+    /// Entering it makes it the place the player respawns after dying.
+    #[serde(default)]
+    pub checkpoint: bool,
+}
+
+/// Effects that fire when an event happens and all conditions hold. In world.yaml a
+/// bare effect such as `- !Message "Hello"` also reads as a trigger without conditions.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Trigger {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub effects: Vec<Effect>,
+}
+
+#[derive(Deserialize)]
+struct TriggerFields {
+    #[serde(default)]
+    conditions: Vec<Condition>,
+    effects: Vec<Effect>,
+}
+
+impl<'de> Deserialize<'de> for Trigger {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // `#[serde(untagged)]` cannot be used: it buffers the input, which loses YAML tags.
+        struct TriggerVisitor;
+
+        impl<'de> Visitor<'de> for TriggerVisitor {
+            type Value = Trigger;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a trigger with conditions and effects, or a single effect")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Trigger, A::Error> {
+                let fields = TriggerFields::deserialize(MapAccessDeserializer::new(map))?;
+                Ok(Trigger { conditions: fields.conditions, effects: fields.effects })
+            }
+
+            fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Trigger, A::Error> {
+                let effect = Effect::deserialize(EnumAccessDeserializer::new(data))?;
+                Ok(Trigger { conditions: vec![], effects: vec![effect] })
+            }
+        }
+
+        deserializer.deserialize_any(TriggerVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DescriptionFragment {
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub text: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,8 +591,55 @@ pub struct Player {
     pub current_node: String,
     pub hp: u32,
     pub max_hp: u32,
-    pub inventory: Vec<String>,
+    pub inventory: ItemStacks,
+    #[serde(default)]
+    pub max_carry_weight: Option<u32>,
+    #[serde(default)]
+    pub max_slots: Option<u32>,
     pub attributes: HashMap<String, String>,
+    /// Number of times each node has been entered, including the starting node.
+    #[serde(default)]
+    pub visits: HashMap<String, u32>,
+    /// Nodes in the order they were first entered.
+    #[serde(default)]
+    pub journal: Vec<String>,
+    /// Items the player has seen lying around or carried.
+    #[serde(default)]
+    pub seen_items: BTreeSet<String>,
+    /// Hidden edges the player has found, as (node_id, target_id).
+    #[serde(default)]
+    pub discovered_edges: BTreeSet<(String, String)>,
+    #[serde(default)]
+    pub statuses: Vec<StatusEffect>,
+    /// The last checkpoint node entered, where the player respawns after dying.
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    /// Set while talking to an NPC; leaving the node ends it.
+    #[serde(default)]
+    pub conversation: Option<Conversation>,
+    /// The node the player entered the current one from, used when fleeing.
+    #[serde(default)]
+    pub came_from: Option<String>,
+}
+
+impl Player {
+    /// HP as a percentage of the maximum, for HP bars.
+    pub fn hp_percent(&self) -> u32 {
+        let percent = (u64::from(self.hp) * 100).checked_div(u64::from(self.max_hp)).unwrap_or(0);
+        u32::try_from(percent).unwrap_or(u32::MAX)
+    }
+
+    /// Records entering `node_id` and returns how many times it has been entered.
+    fn enter(&mut self, node_id: &str) -> u32 {
+        self.current_node = node_id.to_string();
+        self.conversation = None;
+        let visits = self.visits.entry(node_id.to_string()).or_insert(0);
+        *visits += 1;
+        if *visits == 1 {
+            self.journal.push(node_id.to_string());
+        }
+        *visits
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,12 +647,46 @@ pub struct World {
     pub nodes: HashMap<String, Node>,
     pub items: HashMap<String, Item>,
     pub combinations: Vec<Combination>,
+    #[serde(default)]
+    pub npcs: HashMap<String, Npc>,
+    /// Weighted random tables, rolled with `RollTable`.
+    #[serde(default)]
+    pub tables: HashMap<String, Vec<Outcome>>,
+    #[serde(default)]
+    pub flags: HashMap<String, String>,
+    #[serde(default)]
+    pub victory: Vec<Ending>,
+    #[serde(default)]
+    pub defeat: Vec<Ending>,
+    #[serde(default)]
+    pub player: PlayerConfig,
 }
 
-impl World {
-    pub fn get_ascii_map(&self) -> String {
-        "Graph-based world (ASCII map disabled)".to_string()
-    }
+/// How the world sets up its player when a run starts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlayerConfig {
+    /// No limit if not set.
+    #[serde(default)]
+    pub max_carry_weight: Option<u32>,
+    /// No limit if not set.
+    #[serde(default)]
+    pub max_slots: Option<u32>,
+    /// Starting and maximum HP; 100 if not set.
+    #[serde(default)]
+    pub max_hp: Option<u32>,
+    /// Turns `undo` can take back; 20 if not set, 0 disables undo.
+    #[serde(default)]
+    pub history_limit: Option<usize>,
+    /// Ending shown when the player dies without having reached a checkpoint.
+    #[serde(default)]
+    pub death_message: Option<String>,
+}
+
+/// A way for the run to finish, checked after every action.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Ending {
+    pub conditions: Vec<Condition>,
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,6 +696,18 @@ pub struct WorldTemplate {
     pub items: HashMap<String, Item>,
     #[serde(default)]
     pub combinations: Vec<Combination>,
+    #[serde(default)]
+    pub npcs: HashMap<String, Npc>,
+    #[serde(default)]
+    pub tables: HashMap<String, Vec<Outcome>>,
+    #[serde(default)]
+    pub flags: HashMap<String, String>,
+    #[serde(default)]
+    pub victory: Vec<Ending>,
+    #[serde(default)]
+    pub defeat: Vec<Ending>,
+    #[serde(default)]
+    pub player: PlayerConfig,
 }
 
 impl WorldTemplate {
@@ -83,6 +716,12 @@ impl WorldTemplate {
             nodes: world.nodes.clone(),
             items: world.items.clone(),
             combinations: world.combinations.clone(),
+            npcs: world.npcs.clone(),
+            tables: world.tables.clone(),
+            flags: world.flags.clone(),
+            victory: world.victory.clone(),
+            defeat: world.defeat.clone(),
+            player: world.player.clone(),
         }
     }
 
@@ -91,6 +730,12 @@ impl WorldTemplate {
             nodes: self.nodes.clone(),
             items: self.items.clone(),
             combinations: self.combinations.clone(),
+            npcs: self.npcs.clone(),
+            tables: self.tables.clone(),
+            flags: self.flags.clone(),
+            victory: self.victory.clone(),
+            defeat: self.defeat.clone(),
+            player: self.player.clone(),
         }
     }
 
@@ -103,29 +748,158 @@ impl WorldTemplate {
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum GameStatus {
+    #[default]
+    Playing,
+    Won,
+    Lost,
+}
+
+/// How many teleports may follow from each other before the chain is taken to be
+/// a loop, such as two nodes whose `on_enter` teleports to the other.
+const MAX_TELEPORT_DEPTH: u32 = 16;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub player: Player,
     pub world: World,
     pub turn: u32,
     pub log: Vec<String>,
-    pub history: Vec<World>,
+    /// Undo stack, most recent snapshot last.
+    pub history: Vec<Snapshot>,
+    /// Maximum number of snapshots kept in `history`; 0 disables undo.
+    #[serde(default = "default_history_limit")]
+    pub history_limit: usize,
+    #[serde(default)]
+    pub status: GameStatus,
+    /// Message of the ending that finished the run.
+    #[serde(default)]
+    pub ending: Option<String>,
+    /// Everything random in the run draws from this, so it is saved and undone with the rest.
+    #[serde(default)]
+    pub rng: Rng,
+    /// Events of the action currently being applied.
+    #[serde(skip)]
+    events: Vec<GameEvent>,
+    /// Teleports currently being applied, counting those started by the enter
+    /// triggers of a teleport's target.
+    #[serde(skip)]
+    teleport_depth: u32,
 }
 
+/// Everything `undo` needs to restore a previous turn. Of the world it only keeps
+/// what the turn changed, so snapshots are restored newest first. The log is not
+/// part of it, so rewinding never erases what the player has already read.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub player: Player,
+    /// Nodes, items and NPCs as they were before the turn changed them.
+    #[serde(default)]
+    pub nodes: HashMap<String, Node>,
+    #[serde(default)]
+    pub items: HashMap<String, Item>,
+    #[serde(default)]
+    pub npcs: HashMap<String, Npc>,
+    /// Previous value of every flag the turn set; None if it was unset.
+    #[serde(default)]
+    pub flags: HashMap<String, Option<String>>,
+    pub turn: u32,
+    pub status: GameStatus,
+    pub ending: Option<String>,
+    #[serde(default)]
+    pub rng: Rng,
+}
+
+fn default_history_limit() -> usize {
+    20
+}
+
+impl Snapshot {
+    /// Keeps the parts of `before` that differ in `after`, unless an older version
+    /// of them is already kept.
+    fn keep_changes(&mut self, before: World, after: &World) {
+        for (id, node) in before.nodes {
+            if after.nodes.get(&id) != Some(&node) {
+                self.nodes.entry(id).or_insert(node);
+            }
+        }
+        for (id, item) in before.items {
+            if after.items.get(&id) != Some(&item) {
+                self.items.entry(id).or_insert(item);
+            }
+        }
+        for (id, npc) in before.npcs {
+            if after.npcs.get(&id) != Some(&npc) {
+                self.npcs.entry(id).or_insert(npc);
+            }
+        }
+        for key in before.flags.keys().chain(after.flags.keys()) {
+            if before.flags.get(key) != after.flags.get(key) {
+                self.flags.entry(key.clone()).or_insert_with(|| before.flags.get(key).cloned());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum GameAction {
     Help,
     Look,
     Move(String), // target_id
     Explore(Option<String>),
-    Combine(String, String),
-    Pickup(String),
-    Drop(String),
+    Combine(Vec<String>), // item_ids
+    Pickup(String, Option<u32>), // item_id, count (the whole stack if None)
+    Drop(String, Option<u32>), // item_id, count (the whole stack if None)
     Inventory,
-    Use(String),
+    Use(String, Option<String>), // item_id, target
+    Undo(usize), // number of turns
+    Map(bool), // fog_of_war
+    Open(String),
+    Close(String),
+    PutIn(String, String), // item_id, container_id
+    TakeFrom(String, String), // item_id, container_id
+    Talk(String), // npc_id
+    Say(usize), // choice number, from 1
+    Attack(String, Option<String>), // npc_id, weapon item_id
+    Flee,
     Invalid(String),
 }
 
+impl GameAction {
+    /// The text command that `GameState::parse_command` turns back into this action.
+    pub fn to_command(&self) -> String {
+        match self {
+            GameAction::Help => "help".to_string(),
+            GameAction::Look => "look".to_string(),
+            GameAction::Move(target_id) => format!("go {}", target_id),
+            GameAction::Explore(None) => "explore".to_string(),
+            GameAction::Explore(Some(target)) => format!("explore {}", target),
+            GameAction::Combine(item_ids) => format!("combine {}", item_ids.join(" ")),
+            GameAction::Pickup(item_id, None) => format!("pickup {}", item_id),
+            GameAction::Pickup(item_id, Some(count)) => format!("pickup {} {}", count, item_id),
+            GameAction::Drop(item_id, None) => format!("drop {}", item_id),
+            GameAction::Drop(item_id, Some(count)) => format!("drop {} {}", count, item_id),
+            GameAction::Inventory => "inventory".to_string(),
+            GameAction::Use(item_id, None) => format!("use {}", item_id),
+            GameAction::Use(item_id, Some(target)) => format!("use {} on {}", item_id, target),
+            GameAction::Undo(steps) => format!("undo {}", steps),
+            GameAction::Map(true) => "map".to_string(),
+            GameAction::Map(false) => "map all".to_string(),
+            GameAction::Open(container_id) => format!("open {}", container_id),
+            GameAction::Close(container_id) => format!("close {}", container_id),
+            GameAction::PutIn(item_id, container_id) => format!("put {} in {}", item_id, container_id),
+            GameAction::TakeFrom(item_id, container_id) => format!("take {} from {}", item_id, container_id),
+            GameAction::Talk(npc_id) => format!("talk {}", npc_id),
+            GameAction::Say(number) => format!("say {}", number),
+            GameAction::Attack(npc_id, None) => format!("attack {}", npc_id),
+            GameAction::Attack(npc_id, Some(item_id)) => format!("attack {} with {}", npc_id, item_id),
+            GameAction::Flee => "flee".to_string(),
+            GameAction::Invalid(input) => input.clone(),
+        }
+    }
+}
+
 impl GameState {
     pub fn new(player_name: String) -> Self {
         let mut nodes = HashMap::new();
@@ -137,8 +911,21 @@ impl GameState {
                 target_id: "forest".to_string(),
                 label: "Go to the forest".to_string(),
                 conditions: vec![],
+                effects: vec![],
+                hidden: None,
+                hazard: None,
             }],
-            items: vec![],
+            items: ItemStacks::new(),
+            hidden_items: vec![],
+            features: HashMap::new(),
+            first_visit_description: None,
+            description_fragments: vec![],
+            on_enter: vec![],
+            on_first_enter: vec![],
+            on_exit: vec![],
+            position: None,
+            hazard: None,
+            checkpoint: false,
         };
         let forest_node = Node {
             id: "forest".to_string(),
@@ -148,8 +935,21 @@ impl GameState {
                 target_id: "start".to_string(),
                 label: "Return to the start".to_string(),
                 conditions: vec![],
+                effects: vec![],
+                hidden: None,
+                hazard: None,
             }],
-            items: vec![],
+            items: ItemStacks::new(),
+            hidden_items: vec![],
+            features: HashMap::new(),
+            first_visit_description: None,
+            description_fragments: vec![],
+            on_enter: vec![],
+            on_first_enter: vec![],
+            on_exit: vec![],
+            position: None,
+            hazard: None,
+            checkpoint: false,
         };
         nodes.insert(start_node.id.clone(), start_node);
         nodes.insert(forest_node.id.clone(), forest_node);
@@ -158,22 +958,46 @@ impl GameState {
             nodes,
             items: HashMap::new(),
             combinations: vec![],
+            npcs: HashMap::new(),
+            tables: HashMap::new(),
+            flags: HashMap::new(),
+            victory: vec![],
+            defeat: vec![],
+            player: PlayerConfig::default(),
         };
 
-        Self {
+        let mut state = Self {
             player: Player {
                 name: player_name.clone(),
                 current_node: "start".to_string(),
                 hp: 100,
                 max_hp: 100,
-                inventory: Vec::new(),
+                inventory: ItemStacks::new(),
+                max_carry_weight: None,
+                max_slots: None,
                 attributes: HashMap::new(),
+                visits: HashMap::from([("start".to_string(), 1)]),
+                journal: vec!["start".to_string()],
+                seen_items: BTreeSet::new(),
+                discovered_edges: BTreeSet::new(),
+                statuses: vec![],
+                checkpoint: None,
+                conversation: None,
+                came_from: None,
             },
             world,
             turn: 0,
             log: vec![format!("Welcome to the world of Dotiam, {}!", player_name)],
             history: Vec::new(),
-        }
+            history_limit: default_history_limit(),
+            status: GameStatus::Playing,
+            ending: None,
+            rng: Rng::default(),
+            events: Vec::new(),
+            teleport_depth: 0,
+        };
+        state.note_seen_items();
+        state
     }
 
     pub fn new_with_world(player_name: String, world: World) -> Self {
@@ -182,191 +1006,1248 @@ impl GameState {
         } else {
             world.nodes.keys().next().cloned().unwrap_or_default()
         };
+        let history_limit = world.player.history_limit.unwrap_or_else(default_history_limit);
 
-        Self {
+        let mut state = Self {
             player: Player {
                 name: player_name.clone(),
-                current_node,
-                hp: 100,
-                max_hp: 100,
-                inventory: Vec::new(),
+                current_node: current_node.clone(),
+                hp: world.player.max_hp.unwrap_or(100),
+                max_hp: world.player.max_hp.unwrap_or(100),
+                inventory: ItemStacks::new(),
+                max_carry_weight: world.player.max_carry_weight,
+                max_slots: world.player.max_slots,
                 attributes: HashMap::new(),
+                visits: HashMap::from([(current_node.clone(), 1)]),
+                journal: vec![current_node.clone()],
+                seen_items: BTreeSet::new(),
+                discovered_edges: BTreeSet::new(),
+                statuses: vec![],
+                checkpoint: world.nodes.get(&current_node).filter(|n| n.checkpoint).map(|_| current_node.clone()),
+                conversation: None,
+                came_from: None,
             },
             world,
             turn: 0,
             log: vec![format!("Welcome to the world of Dotiam, {}!", player_name)],
             history: Vec::new(),
+            history_limit,
+            status: GameStatus::Playing,
+            ending: None,
+            rng: Rng::default(),
+            events: Vec::new(),
+            teleport_depth: 0,
+        };
+        // Starting the run is the first entry into the start node
+        let start = state.player.current_node.clone();
+        if let Err(err) = state.arrive(&start, true) {
+            state.emit(GameEvent::EffectFailed(err));
         }
+        state.note_seen_items();
+        state.render_events();
+        state
     }
 
     pub fn get_current_description(&self) -> String {
         match self.world.nodes.get(&self.player.current_node) {
-            Some(node) => node.description.clone(),
+            Some(node) => {
+                let first_visit = self.player.visits.get(&node.id).copied().unwrap_or(0) <= 1;
+                let mut description = match &node.first_visit_description {
+                    Some(text) if first_visit => text.clone(),
+                    _ => node.description.clone(),
+                };
+                for fragment in &node.description_fragments {
+                    if self.check_conditions(&fragment.conditions) {
+                        description.push(' ');
+                        description.push_str(&fragment.text);
+                    }
+                }
+                description
+            }
             None => "You are lost in the void.".to_string(),
         }
     }
 
     pub fn can_traverse(&self, edge: &Edge) -> bool {
-        for condition in &edge.conditions {
-            match &condition.condition_type {
-                ConditionType::HasItem(item) => {
-                    if !self.player.inventory.contains(item) {
-                        return false;
-                    }
-                }
-                ConditionType::HasAttribute(key, value) => {
-                    if self.player.attributes.get(key) != Some(value) {
-                        return false;
-                    }
-                }
-                ConditionType::MinHP(min_hp) => {
-                    if self.player.hp < *min_hp {
-                        return false;
-                    }
-                }
-            }
-        }
-        true
+        self.check_conditions(&edge.conditions)
     }
 
-    pub fn apply_action(&mut self, action: GameAction) {
-        match action {
-            GameAction::Help => {
-                self.log.push("Available commands:".to_string());
-                self.log.push("  h, help          - Show this help".to_string());
-                self.log.push("  l, look          - Look at the current scene".to_string());
-                self.log.push("  g, go <target>   - Go to a specific place".to_string());
-                self.log.push("  x, explore [obj] - Explore the scene or an object".to_string());
-                self.log.push("  c, combine <a> <b> - Combine two items".to_string());
-                self.log.push("  p, pickup <item> - Pick up an item".to_string());
-                self.log.push("  d, drop <item>   - Drop an item".to_string());
-                self.log.push("  i, inventory     - Show your inventory".to_string());
-                self.log.push("  u, use <item>    - Use an item".to_string());
+    /// Whether the player knows about an edge leaving `node_id`.
+    pub fn is_edge_known(&self, node_id: &str, edge: &Edge) -> bool {
+        edge.hidden.is_none() || self.player.discovered_edges.contains(&(node_id.to_string(), edge.target_id.clone()))
+    }
+
+    /// Edges out of the current node that the player knows about.
+    pub fn visible_edges(&self) -> Vec<&Edge> {
+        match self.world.nodes.get(&self.player.current_node) {
+            Some(node) => node.edges.iter().filter(|e| self.is_edge_known(&node.id, e)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn note_seen_items(&mut self) {
+        let reachable = self.reachable_items();
+        self.player.seen_items.extend(reachable);
+    }
+
+    /// Items the player can get at: the inventory, the items lying in the current
+    /// node and the contents of open containers among them, nested ones included.
+    pub fn reachable_items(&self) -> Vec<String> {
+        let mut items: Vec<String> = Vec::new();
+        for (_, stack) in self.reachable_stacks() {
+            if !items.contains(&stack.item_id) {
+                items.push(stack.item_id.clone());
+            }
+        }
+        items
+    }
+
+    /// Like `reachable_items`, but every stack on its own, with where it lies.
+    fn reachable_stacks(&self) -> Vec<(StackPath, &ItemStack)> {
+        let node_items = self.world.nodes.get(&self.player.current_node).map(|n| &n.items);
+        // Each stack remembers the definitions opened on the way to it, so that a
+        // container whose definition holds itself is only looked into once.
+        let mut found: Vec<(StackPath, &ItemStack, Vec<&str>)> = Vec::new();
+        for (in_inventory, stacks) in [(true, Some(&self.player.inventory)), (false, node_items)] {
+            for (index, stack) in stacks.into_iter().flatten().enumerate() {
+                found.push((StackPath { in_inventory, indexes: vec![index] }, stack, Vec::new()));
+            }
+        }
+        let mut i = 0;
+        while i < found.len() {
+            let (path, stack, visited) = &found[i];
+            let from_definition = stack.container.is_none();
+            if let Some(container) = self.container_of(stack).filter(|c| c.open)
+                && !(from_definition && visited.contains(&stack.item_id.as_str()))
+            {
+                let mut visited = visited.clone();
+                if from_definition {
+                    visited.push(&stack.item_id);
+                }
+                let inner: Vec<_> = container
+                    .contents
+                    .iter()
+                    .enumerate()
+                    .map(|(index, inner)| {
+                        let mut path = path.clone();
+                        path.indexes.push(index);
+                        (path, inner, visited.clone())
+                    })
+                    .collect();
+                found.extend(inner);
+            }
+            i += 1;
+        }
+        found.into_iter().map(|(path, stack, _)| (path, stack)).collect()
+    }
+
+    /// The container of a unit: its own once it has changed, otherwise the one its
+    /// item starts out with.
+    pub fn container_of<'a>(&'a self, stack: &'a ItemStack) -> Option<&'a Container> {
+        stack.container.as_ref().or_else(|| self.world.items.get(&stack.item_id)?.container.as_ref())
+    }
+
+    /// The value of `key` in a unit's state: its own once it has changed, otherwise
+    /// the one its item starts out with.
+    pub fn state_of<'a>(&'a self, stack: &'a ItemStack, key: &str) -> Option<&'a String> {
+        stack.state.get(key).or_else(|| self.world.items.get(&stack.item_id)?.state.get(key))
+    }
+
+    /// The containers the player can get at, as `open <id>` would find them.
+    pub fn reachable_containers(&self) -> Vec<(String, &Container)> {
+        self.reachable_items()
+            .into_iter()
+            .filter_map(|id| {
+                let container = self.reachable_container(&id, |_, _| true).ok()?.1;
+                Some((id, container))
+            })
+            .collect()
+    }
+
+    /// Weight of a stack, including whatever its units hold.
+    pub fn stack_weight(&self, stack: &ItemStack) -> u32 {
+        self.weight_with_contents(stack, &mut Vec::new())
+    }
+
+    /// `visited` holds the definitions whose contents are being weighed further
+    /// up, so a definition that holds itself is only counted once.
+    fn weight_with_contents(&self, stack: &ItemStack, visited: &mut Vec<String>) -> u32 {
+        let Some(item) = self.world.items.get(&stack.item_id) else {
+            return 0;
+        };
+        let from_definition = stack.container.is_none();
+        let contents = match self.container_of(stack) {
+            Some(_) if from_definition && visited.contains(&item.id) => 0,
+            Some(container) => {
+                if from_definition {
+                    visited.push(item.id.clone());
+                }
+                let weight = container
+                    .contents
+                    .iter()
+                    .map(|s| self.weight_with_contents(s, visited))
+                    .fold(0, u32::saturating_add);
+                if from_definition {
+                    visited.pop();
+                }
+                weight
+            }
+            None => 0,
+        };
+        item.weight.saturating_add(contents).saturating_mul(stack.count)
+    }
+
+    pub fn carried_weight(&self) -> u32 {
+        self.player.inventory.iter().map(|s| self.stack_weight(s)).fold(0, u32::saturating_add)
+    }
+
+    /// Inventory slots in use. Items unknown to the world take one slot.
+    pub fn used_slots(&self) -> u32 {
+        self.player.inventory.ids().map(|id| self.world.items.get(id).map_or(1, |i| i.bulk)).fold(0, u32::saturating_add)
+    }
+
+    /// Whether `incoming` units of `item_id` fit within the player's limits.
+    fn check_capacity(&self, item_id: &str, incoming: &ItemStacks) -> Result<(), GameEvent> {
+        if let Some(max) = self.player.max_carry_weight {
+            let carried = self.carried_weight();
+            let weight = incoming.iter().map(|s| self.stack_weight(s)).fold(carried, u32::saturating_add);
+            if weight > max {
+                return Err(GameEvent::TooHeavy { item_id: item_id.to_string(), carried, max });
+            }
+        }
+        if let Some(max) = self.player.max_slots
+            && !self.player.inventory.contains(item_id)
+        {
+            let bulk = self.world.items.get(item_id).map_or(1, |i| i.bulk);
+            if self.used_slots().saturating_add(bulk) > max {
+                return Err(GameEvent::InventoryFull { item_id: item_id.to_string(), max_slots: max });
             }
+        }
+        Ok(())
+    }
+
+    /// The reachable units called `item_id`, with where they lie.
+    fn reachable_units(&self, item_id: &str) -> Vec<(StackPath, &ItemStack)> {
+        self.reachable_stacks().into_iter().filter(|(_, s)| s.item_id == item_id).collect()
+    }
+
+    /// The reachable container called `container_id`, or the event explaining why
+    /// not. Of several units the first that `suits` is preferred, then the first.
+    fn reachable_container(
+        &self,
+        container_id: &str,
+        suits: impl Fn(&StackPath, &Container) -> bool,
+    ) -> Result<(StackPath, &Container), GameEvent> {
+        let units = self.reachable_units(container_id);
+        if units.is_empty() {
+            return Err(GameEvent::ItemNotVisible(container_id.to_string()));
+        }
+        let mut containers: Vec<(StackPath, &Container)> =
+            units.into_iter().filter_map(|(path, unit)| Some((path, self.container_of(unit)?))).collect();
+        if containers.is_empty() {
+            return Err(GameEvent::NotAContainer(container_id.to_string()));
+        }
+        let index = containers.iter().position(|(path, container)| suits(path, container)).unwrap_or(0);
+        Ok(containers.swap_remove(index))
+    }
+
+    /// Like `reachable_container`, but the container must also be open.
+    fn open_container(&self, container_id: &str, suits: impl Fn(&Container) -> bool) -> Result<(StackPath, &Container), GameEvent> {
+        let (path, container) = self.reachable_container(container_id, |_, c| c.open && suits(c))?;
+        if container.open {
+            Ok((path, container))
+        } else {
+            Err(GameEvent::ContainerIsClosed(container_id.to_string()))
+        }
+    }
+
+    /// The container of the unit at `path`, split off its stack and given its own
+    /// copy of the container so that changing it leaves the other units alone.
+    fn container_mut(&mut self, path: &StackPath) -> Option<&mut Container> {
+        let (items, stacks, index) = self.stacks_mut(path)?;
+        own_container(items, stacks.single_mut(index))
+    }
+
+    /// The stacks holding the unit at `path` and its index among them, along with
+    /// the item definitions. Containers on the way get contents of their own.
+    fn stacks_mut(&mut self, path: &StackPath) -> Option<(&HashMap<String, Item>, &mut ItemStacks, usize)> {
+        let items = &self.world.items;
+        let mut stacks = if path.in_inventory {
+            &mut self.player.inventory
+        } else {
+            &mut self.world.nodes.get_mut(&self.player.current_node)?.items
+        };
+        let (last, parents) = path.indexes.split_last()?;
+        for &index in parents {
+            stacks = &mut own_container(items, stacks.single_mut(index))?.contents;
+        }
+        Some((items, stacks, *last))
+    }
+
+    fn open(&mut self, container_id: String) {
+        let (path, container) = match self.reachable_container(&container_id, |_, c| !c.open) {
+            Ok((path, container)) => (path, container.clone()),
+            Err(event) => return self.emit(event),
+        };
+        if container.open {
+            return self.emit(GameEvent::AlreadyOpen(container_id));
+        }
+        if container.locked {
+            if !self.check_conditions(&container.unlock_conditions) {
+                return self.emit(GameEvent::ContainerLocked(container_id));
+            }
+            self.emit(GameEvent::ContainerUnlocked(container_id.clone()));
+        }
+        if let Some(container) = self.container_mut(&path) {
+            container.locked = false;
+            container.open = true;
+        }
+        self.emit(GameEvent::ContainerOpened { container_id, contents: container.contents });
+        self.turn += 1;
+    }
+
+    fn close(&mut self, container_id: String) {
+        match self.reachable_container(&container_id, |_, c| c.open) {
+            Ok((_, container)) if !container.open => self.emit(GameEvent::AlreadyClosed(container_id)),
+            Ok((path, _)) => {
+                if let Some(container) = self.container_mut(&path) {
+                    container.open = false;
+                }
+                self.emit(GameEvent::ContainerClosed(container_id));
+                self.turn += 1;
+            }
+            Err(event) => self.emit(event),
+        }
+    }
+
+    fn put_in(&mut self, item_id: String, container_id: String) {
+        if !self.player.inventory.contains(&item_id) {
+            return self.emit(GameEvent::NotCarrying(item_id));
+        }
+        // Everything carried of the item moves, so the container must not be one of
+        // those units or lie inside one
+        let moving = |path: &StackPath| path.in_inventory && self.player.inventory.get(path.indexes[0]).is_some_and(|s| s.item_id == item_id);
+        let path = match self.reachable_container(&container_id, |path, c| c.open && !moving(path)) {
+            Ok((path, _)) if moving(&path) => return self.emit(GameEvent::CannotPutInside { item_id, container_id }),
+            Ok((_, container)) if !container.open => return self.emit(GameEvent::ContainerIsClosed(container_id)),
+            Ok((path, _)) => path,
+            Err(event) => return self.emit(event),
+        };
+        let moved = self.player.inventory.clone().take_all(&item_id);
+        let count = moved.total();
+        if let Some(container) = self.container_mut(&path) {
+            container.contents.append(moved);
+        }
+        self.player.inventory.take_all(&item_id);
+        self.emit(GameEvent::ItemPutIn { item_id, container_id, count });
+        self.turn += 1;
+    }
+
+    fn take_from(&mut self, item_id: String, container_id: String) {
+        let (path, taken) = match self.open_container(&container_id, |c| c.contents.contains(&item_id)) {
+            Ok((path, container)) => (path, container.contents.clone().take_all(&item_id)),
+            Err(event) => return self.emit(event),
+        };
+        if taken.is_empty() {
+            return self.emit(GameEvent::NotInContainer { item_id, container_id });
+        }
+        let Some(item) = self.world.items.get(&item_id).cloned() else {
+            return self.emit(GameEvent::NotInContainer { item_id, container_id });
+        };
+        if !item.can_pickup {
+            return self.emit(GameEvent::CannotPickUp(item_id));
+        }
+        if let Err(event) = self.check_capacity(&item_id, &taken) {
+            return self.emit(event);
+        }
+        let count = taken.total();
+        let result = self.atomically(|state| {
+            if let Some(container) = state.container_mut(&path) {
+                container.contents.take_all(&item_id);
+            }
+            state.player.inventory.append(taken);
+            state.emit(GameEvent::ItemTakenFrom { item_id: item_id.clone(), container_id: container_id.clone(), count });
+            state.apply_effects(&item.on_pickup)
+        });
+        match result {
+            Ok(()) => self.turn += 1,
+            Err(err) => self.emit(GameEvent::EffectFailed(err)),
+        }
+    }
+
+    /// Marks a hidden edge as found. Returns false if it was already known.
+    fn discover_edge(&mut self, node_id: &str, target_id: &str, message: Option<String>) -> Result<bool, EffectError> {
+        let edge = self
+            .world
+            .nodes
+            .get(node_id)
+            .and_then(|n| n.edges.iter().find(|e| e.target_id == target_id))
+            .ok_or_else(|| EffectError::NoSuchEdge(node_id.to_string(), target_id.to_string()))?;
+        if self.is_edge_known(node_id, edge) {
+            return Ok(false);
+        }
+        let label = edge.label.clone();
+        self.player.discovered_edges.insert((node_id.to_string(), target_id.to_string()));
+        self.emit(GameEvent::EdgeDiscovered {
+            node_id: node_id.to_string(),
+            target_id: target_id.to_string(),
+            label,
+            message,
+        });
+        Ok(true)
+    }
+
+    /// Reveals what exploring `feature` (or the whole room for `None`) finds in
+    /// the current node right now. Found items join the node's visible items.
+    /// Returns the number of discoveries.
+    fn reveal_secrets(&mut self, feature: Option<&str>) -> usize {
+        let node_id = self.player.current_node.clone();
+        let Some(node) = self.world.nodes.get(&node_id).cloned() else {
+            return 0;
+        };
+        let matches = |wanted: &Option<String>| match (wanted, feature) {
+            (None, None) => true,
+            (Some(wanted), Some(feature)) => wanted.eq_ignore_ascii_case(feature),
+            _ => false,
+        };
+
+        let mut found = 0;
+        for edge in &node.edges {
+            if let Some(discovery) = &edge.hidden
+                && matches(&discovery.feature)
+                && self.check_conditions(&discovery.conditions)
+                && let Ok(true) = self.discover_edge(&node_id, &edge.target_id, discovery.message.clone())
+            {
+                found += 1;
+            }
+        }
+        for hidden in &node.hidden_items {
+            if matches(&hidden.feature) && self.check_conditions(&hidden.conditions) {
+                if let Some(node) = self.world.nodes.get_mut(&node_id) {
+                    node.hidden_items.retain(|h| h != hidden);
+                    node.items.add(&hidden.item_id, 1);
+                }
+                self.emit(GameEvent::ItemDiscovered {
+                    node_id: node_id.clone(),
+                    item_id: hidden.item_id.clone(),
+                    message: hidden.message.clone(),
+                });
+                found += 1;
+            }
+        }
+        found
+    }
+
+    /// Evaluates a list of conditions as an implicit AND.
+    pub fn check_conditions(&self, conditions: &[Condition]) -> bool {
+        conditions.iter().all(|condition| self.check_condition(condition))
+    }
+
+    pub fn check_condition(&self, condition: &Condition) -> bool {
+        match &condition.condition_type {
+            ConditionType::HasItem(item) => self.player.inventory.contains(item),
+            ConditionType::HasAttribute(key, value) => self.player.attributes.get(key) == Some(value),
+            ConditionType::MinHP(min_hp) => self.player.hp >= *min_hp,
+            ConditionType::MaxHP(max_hp) => self.player.hp <= *max_hp,
+            ConditionType::AttributeCompare(key, comparison, value) => self.player.attributes.get(key)
+                .and_then(|v| v.trim().parse::<i64>().ok())
+                .is_some_and(|v| comparison.compare(v, *value)),
+            ConditionType::TurnAtLeast(turn) => self.turn >= *turn,
+            ConditionType::TurnBefore(turn) => self.turn < *turn,
+            ConditionType::HasVisited(node_id) => self.player.visits.contains_key(node_id),
+            ConditionType::HasSeen(item_id) => self.player.seen_items.contains(item_id),
+            ConditionType::EdgeDiscovered(node_id, target_id) => {
+                self.player.discovered_edges.contains(&(node_id.clone(), target_id.clone()))
+            }
+            ConditionType::AtNode(node_id) => &self.player.current_node == node_id,
+            ConditionType::ItemAt(item_id, node_id) => self.world.nodes.get(node_id)
+                .is_some_and(|n| n.items.contains(item_id)),
+            ConditionType::NodeAttribute(node_id, key, value) => self.world.nodes.get(node_id)
+                .is_some_and(|n| n.attributes.get(key) == Some(value)),
+            ConditionType::NpcDefeated(npc_id) => self.world.npcs.get(npc_id).is_some_and(|n| n.is_defeated()),
+            ConditionType::WorldFlag(key, value) => self.world.flags.get(key) == Some(value),
+            ConditionType::CarriedWeight(comparison, value) => comparison.compare(self.carried_weight() as i64, *value),
+            ConditionType::ItemState(item_id, key, value) => self.reachable_units(item_id)
+                .iter()
+                .any(|(_, unit)| self.state_of(unit, key) == Some(value)),
+            ConditionType::HasStatus(status_id) => self.player.statuses.iter().any(|s| &s.id == status_id),
+            ConditionType::NpcAttribute(npc_id, key, value) => self.world.npcs.get(npc_id)
+                .is_some_and(|n| n.attributes.get(key) == Some(value)),
+            ConditionType::All(conditions) => self.check_conditions(conditions),
+            ConditionType::Any(conditions) => conditions.iter().any(|c| self.check_condition(c)),
+            ConditionType::Not(conditions) => !self.check_conditions(conditions),
+        }
+    }
+
+    /// Applies all effects or none of them: if any effect fails, the player, world,
+    /// status and pending events are restored to what they were before the first effect.
+    pub fn apply_effects(&mut self, effects: &[Effect]) -> Result<(), EffectError> {
+        if effects.is_empty() {
+            return Ok(());
+        }
+        self.atomically(|state| effects.iter().try_for_each(|effect| state.apply_effect(effect)))
+    }
+
+    /// Runs every trigger whose conditions hold, in order. Conditions are checked
+    /// just before each trigger fires, so earlier triggers can enable later ones.
+    pub fn run_triggers(&mut self, triggers: &[Trigger]) -> Result<(), EffectError> {
+        if triggers.is_empty() {
+            return Ok(());
+        }
+        self.atomically(|state| {
+            for trigger in triggers {
+                if state.check_conditions(&trigger.conditions) {
+                    state.apply_effects(&trigger.effects)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs `f` and restores the player, world, status, ending and pending events if it fails.
+    fn atomically(&mut self, f: impl FnOnce(&mut Self) -> Result<(), EffectError>) -> Result<(), EffectError> {
+        let player = self.player.clone();
+        let world = self.world.clone();
+        let status = self.status.clone();
+        let ending = self.ending.clone();
+        let rng = self.rng;
+        let events_len = self.events.len();
+
+        let result = f(self);
+        if result.is_err() {
+            self.player = player;
+            self.world = world;
+            self.status = status;
+            self.ending = ending;
+            self.rng = rng;
+            self.events.truncate(events_len);
+        }
+        result
+    }
+
+    fn emit(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+
+    fn apply_effect(&mut self, effect: &Effect) -> Result<(), EffectError> {
+        match effect {
+            Effect::SetAttribute(key, value) => {
+                self.player.attributes.insert(key.clone(), value.clone());
+                self.emit(GameEvent::AttributeChanged { key: key.clone(), value: Some(value.clone()) });
+            }
+            Effect::UnsetAttribute(key) => {
+                self.player.attributes.remove(key);
+                self.emit(GameEvent::AttributeChanged { key: key.clone(), value: None });
+            }
+            Effect::AdjustHP(delta) => {
+                let from = self.adjust_hp(*delta);
+                self.emit(GameEvent::HpChanged { from, to: self.player.hp });
+            }
+            Effect::GiveItem(item_id) => {
+                self.player.inventory.add(item_id, 1);
+                self.emit(GameEvent::ItemReceived(item_id.clone()));
+            }
+            Effect::RemoveItem(item_id) => {
+                if !self.player.inventory.remove(item_id, 1) {
+                    return Err(EffectError::MissingItem(item_id.clone()));
+                }
+                self.emit(GameEvent::ItemRemoved(item_id.clone()));
+            }
+            Effect::SpawnItem(item_id, node_id) => {
+                self.node_mut(node_id)?.items.add(item_id, 1);
+                self.emit(GameEvent::ItemSpawned { item_id: item_id.clone(), node_id: node_id.clone() });
+            }
+            Effect::AddEdge(node_id, edge) => {
+                self.node_mut(node_id)?.edges.push(edge.clone());
+                self.emit(GameEvent::EdgeAdded { node_id: node_id.clone(), target_id: edge.target_id.clone() });
+            }
+            Effect::RemoveEdge(node_id, target_id) => {
+                let node = self.node_mut(node_id)?;
+                let pos = node.edges.iter().position(|e| &e.target_id == target_id)
+                    .ok_or_else(|| EffectError::NoSuchEdge(node_id.clone(), target_id.clone()))?;
+                node.edges.remove(pos);
+                self.emit(GameEvent::EdgeRemoved { node_id: node_id.clone(), target_id: target_id.clone() });
+            }
+            Effect::RevealEdge(node_id, target_id) => {
+                self.discover_edge(node_id, target_id, None)?;
+            }
+            Effect::SetDescription(node_id, description) => {
+                self.node_mut(node_id)?.description = description.clone();
+                self.emit(GameEvent::DescriptionChanged(node_id.clone()));
+            }
+            Effect::SetFlag(key, value) => {
+                self.world.flags.insert(key.clone(), value.clone());
+                self.emit(GameEvent::FlagChanged { key: key.clone(), value: value.clone() });
+            }
+            Effect::SetItemState(item_id, key, value) => {
+                let item = self.world.items.get(item_id).ok_or_else(|| EffectError::UnknownItem(item_id.clone()))?;
+                let initial = item.state.get(key).cloned();
+                // A unit that actually changes is preferred, so that using one of two
+                // unlit lamps and then the other lights both
+                let units = self.reachable_units(item_id);
+                let unit = units.iter().find(|(_, unit)| self.state_of(unit, key) != Some(value)).or(units.first());
+                let path = unit.map(|(path, _)| path.clone()).ok_or_else(|| EffectError::MissingItem(item_id.clone()))?;
+                let (_, stacks, index) = self.stacks_mut(&path).ok_or_else(|| EffectError::MissingItem(item_id.clone()))?;
+                let unit = stacks.single_mut(index);
+                if initial.as_ref() == Some(value) {
+                    unit.state.remove(key);
+                } else {
+                    unit.state.insert(key.clone(), value.clone());
+                }
+                stacks.regroup();
+                self.emit(GameEvent::ItemStateChanged { item_id: item_id.clone(), key: key.clone(), value: value.clone() });
+            }
+            Effect::AddStatus(status) => {
+                self.player.statuses.retain(|s| s.id != status.id);
+                self.player.statuses.push(status.clone());
+                self.emit(GameEvent::StatusAdded(status.id.clone()));
+            }
+            Effect::RemoveStatus(status_id) => {
+                if let Some(pos) = self.player.statuses.iter().position(|s| &s.id == status_id) {
+                    self.player.statuses.remove(pos);
+                    self.emit(GameEvent::StatusRemoved(status_id.clone()));
+                }
+            }
+            Effect::SetNpcAttribute(npc_id, key, value) => {
+                let npc = self.world.npcs.get_mut(npc_id).ok_or_else(|| EffectError::UnknownNpc(npc_id.clone()))?;
+                npc.attributes.insert(key.clone(), value.clone());
+                self.emit(GameEvent::NpcAttributeChanged { npc_id: npc_id.clone(), key: key.clone(), value: value.clone() });
+            }
+            Effect::MoveNpc(npc_id, node_id) => {
+                if !self.world.nodes.contains_key(node_id) {
+                    return Err(EffectError::UnknownNode(node_id.clone()));
+                }
+                let npc = self.world.npcs.get_mut(npc_id).ok_or_else(|| EffectError::UnknownNpc(npc_id.clone()))?;
+                npc.node_id = node_id.clone();
+                if self.player.conversation.as_ref().is_some_and(|c| &c.npc_id == npc_id) {
+                    self.player.conversation = None;
+                }
+                self.emit(GameEvent::NpcMoved { npc_id: npc_id.clone(), node_id: node_id.clone() });
+            }
+            Effect::Random(outcomes) => self.roll_outcome(None, outcomes)?,
+            Effect::RollTable(table_id) => self.roll_table(table_id)?,
+            Effect::Message(message) => {
+                self.emit(GameEvent::Message(message.clone()));
+            }
+            Effect::Teleport(node_id) => {
+                if !self.world.nodes.contains_key(node_id) {
+                    return Err(EffectError::UnknownNode(node_id.clone()));
+                }
+                if self.teleport_depth >= MAX_TELEPORT_DEPTH {
+                    return Err(EffectError::TeleportLoop(node_id.clone()));
+                }
+                self.teleport_depth += 1;
+                let result = self.teleport(node_id);
+                self.teleport_depth -= 1;
+                result?;
+            }
+            Effect::EndGame(status, message) => {
+                self.status = status.clone();
+                self.ending = Some(message.clone());
+                self.emit(GameEvent::GameEnded { status: status.clone(), message: message.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    /// The feature of the current node called `name`, if it is there right now.
+    pub fn find_feature(&self, name: &str) -> Option<&Feature> {
+        let node = self.world.nodes.get(&self.player.current_node)?;
+        let mut features: Vec<&Feature> = node.features.values().collect();
+        features.sort_by(|a, b| a.id.cmp(&b.id));
+        features.into_iter().find(|f| f.is_called(name) && self.check_conditions(&f.conditions))
+    }
+
+    fn use_target_matches(&self, rule_target: Option<&UseTarget>, target: Option<&str>) -> bool {
+        let here = &self.player.current_node;
+        match (rule_target, target) {
+            (None, None) => true,
+            (Some(UseTarget::Node(node_id)), None) => node_id == here,
+            (Some(UseTarget::Node(node_id)), Some(target)) => node_id == here && node_id.eq_ignore_ascii_case(target),
+            (Some(UseTarget::Item(item_id)), Some(target)) => {
+                item_id.eq_ignore_ascii_case(target)
+                    && (self.player.inventory.contains(item_id)
+                        || self.world.nodes.get(here).is_some_and(|n| n.items.contains(item_id)))
+            }
+            (Some(UseTarget::Feature(feature)), Some(target)) => {
+                feature.eq_ignore_ascii_case(target) || self.find_feature(target).is_some_and(|f| f.id == *feature)
+            }
+            _ => false,
+        }
+    }
+
+    fn use_item(&mut self, item_id: &str, target: Option<&str>) {
+        let mut matching = self.world.items.get(item_id).map(|i| i.use_rules.clone()).unwrap_or_default();
+        matching.retain(|r| self.use_target_matches(r.target.as_ref(), target));
+        // Rules on the feature apply when the item itself knows nothing about it
+        if matching.is_empty()
+            && let Some(feature) = target.and_then(|t| self.find_feature(t))
+        {
+            matching = feature
+                .on_use
+                .iter()
+                .filter(|u| u.item.as_deref() == Some(item_id))
+                .map(|u| UseRule {
+                    target: Some(UseTarget::Feature(feature.id.clone())),
+                    conditions: u.conditions.clone(),
+                    effects: u.effects.clone(),
+                    consume: u.consume,
+                    message: u.message.clone(),
+                })
+                .collect();
+        }
+        if matching.is_empty()
+            && target.is_none()
+            && let Some(item) = self.world.items.get(item_id).filter(|i| i.is_consumable()).cloned()
+        {
+            return self.consume(item);
+        }
+        let Some(rule) = matching.iter().find(|r| self.check_conditions(&r.conditions)) else {
+            let (item_id, target) = (item_id.to_string(), target.map(str::to_string));
+            if !matching.is_empty() {
+                self.emit(GameEvent::UseBlocked { item_id, target });
+            } else {
+                self.emit(GameEvent::NothingHappens { item_id, target });
+            }
+            return;
+        };
+
+        let mut effects = rule.effects.clone();
+        if rule.consume {
+            effects.insert(0, Effect::RemoveItem(item_id.to_string()));
+        }
+        let used = GameEvent::ItemUsed {
+            item_id: item_id.to_string(),
+            target: target.map(str::to_string),
+            message: rule.message.clone(),
+        };
+        let result = self.atomically(|state| {
+            state.emit(used);
+            state.apply_effects(&effects)
+        });
+        match result {
+            Ok(()) => self.turn += 1,
+            Err(err) => self.emit(GameEvent::EffectFailed(err)),
+        }
+    }
+
+    fn move_to(&mut self, target_id: String) {
+        let current_node = self.world.nodes.get(&self.player.current_node).cloned();
+        if let Some(node) = current_node {
+            if let Some(edge) = node.edges.iter().find(|e| e.target_id == target_id && self.is_edge_known(&node.id, e)) {
+                if self.can_traverse(edge) {
+                    let result = self.atomically(|state| {
+                        state.run_triggers(&node.on_exit)?;
+                        state.player.came_from = Some(node.id.clone());
+                        let first_visit = state.player.enter(&target_id) == 1;
+                        state.emit(GameEvent::Moved {
+                            from: node.id.clone(),
+                            to: target_id.clone(),
+                            label: edge.label.clone(),
+                        });
+                        state.apply_effects(&edge.effects)?;
+                        state.suffer(edge.hazard.as_ref());
+                        state.arrive(&target_id, first_visit)
+                    });
+                    match result {
+                        Ok(()) => self.turn += 1,
+                        Err(err) => self.emit(GameEvent::EffectFailed(err)),
+                    }
+                } else {
+                    let unmet = edge.conditions.iter().filter(|c| !self.check_condition(c)).cloned().collect();
+                    self.emit(GameEvent::MoveBlocked {
+                        target_id: target_id.clone(),
+                        label: edge.label.clone(),
+                        unmet,
+                    });
+                }
+            } else {
+                self.emit(GameEvent::NoPath(target_id));
+            }
+        }
+    }
+
+    /// Leaves the current node for `node_id` without an edge.
+    fn teleport(&mut self, node_id: &str) -> Result<(), EffectError> {
+        let from = self.player.current_node.clone();
+        if let Some(on_exit) = self.world.nodes.get(&from).map(|n| n.on_exit.clone()) {
+            self.run_triggers(&on_exit)?;
+        }
+        self.player.came_from = Some(from);
+        let first_visit = self.player.enter(node_id) == 1;
+        self.emit(GameEvent::Teleported(node_id.to_string()));
+        self.arrive(node_id, first_visit)
+    }
+
+    /// Everything that happens on entering a node, whether by an edge or a teleport:
+    /// the checkpoint, the enter triggers and the node's hazard.
+    fn arrive(&mut self, node_id: &str, first_visit: bool) -> Result<(), EffectError> {
+        let Some(target) = self.world.nodes.get(node_id).cloned() else {
+            return Ok(());
+        };
+        if target.checkpoint && self.player.checkpoint.as_deref() != Some(node_id) {
+            self.player.checkpoint = Some(node_id.to_string());
+            self.emit(GameEvent::CheckpointReached(node_id.to_string()));
+        }
+        if first_visit {
+            self.run_triggers(&target.on_first_enter)?;
+        }
+        self.run_triggers(&target.on_enter)?;
+        self.suffer(target.hazard.as_ref());
+        Ok(())
+    }
+
+    /// Eats or drinks one unit of a healing item.
+    fn consume(&mut self, item: Item) {
+        let cures: Vec<String> =
+            item.cures.iter().filter(|c| self.player.statuses.iter().any(|s| &s.id == *c)).cloned().collect();
+        if self.player.hp == self.player.max_hp && cures.is_empty() {
+            return self.emit(GameEvent::NoNeedToConsume(item.id));
+        }
+        self.player.inventory.remove(&item.id, 1);
+        let from = self.adjust_hp(i32::try_from(item.heals).unwrap_or(i32::MAX));
+        self.emit(GameEvent::ItemConsumed { item_id: item.id, healed: self.player.hp - from });
+        for status_id in cures {
+            self.player.statuses.retain(|s| s.id != status_id);
+            self.emit(GameEvent::StatusRemoved(status_id));
+        }
+        self.turn += 1;
+    }
+
+    /// Changes HP within `0..=max_hp` and returns the previous value.
+    fn adjust_hp(&mut self, delta: i32) -> u32 {
+        let from = self.player.hp;
+        let hp = (self.player.hp as i64 + delta as i64).clamp(0, self.player.max_hp as i64);
+        self.player.hp = hp as u32;
+        from
+    }
+
+    fn suffer(&mut self, hazard: Option<&Hazard>) {
+        let Some(hazard) = hazard.filter(|h| self.check_conditions(&h.conditions)) else {
+            return;
+        };
+        let from = self.adjust_hp(-i32::try_from(hazard.damage).unwrap_or(i32::MAX));
+        self.emit(GameEvent::HazardHit { damage: from - self.player.hp, message: hazard.message.clone() });
+    }
+
+    /// Applies every status effect once and counts down their durations.
+    fn tick_statuses(&mut self) {
+        for status in self.player.statuses.clone() {
+            let from = self.adjust_hp(status.hp_per_turn);
+            if from != self.player.hp {
+                self.emit(GameEvent::StatusTicked { status_id: status.id, from, to: self.player.hp });
+            }
+        }
+        let mut expired = Vec::new();
+        self.player.statuses.retain_mut(|status| match &mut status.turns {
+            Some(turns) if *turns <= 1 => {
+                expired.push(status.id.clone());
+                false
+            }
+            Some(turns) => {
+                *turns -= 1;
+                true
+            }
+            None => true,
+        });
+        for status_id in expired {
+            self.emit(GameEvent::StatusRemoved(status_id));
+        }
+    }
+
+    /// At 0 HP the player respawns at the last checkpoint with full HP and no
+    /// statuses, keeping the inventory. Without a checkpoint the run is lost.
+    /// Defeat endings are checked first, so a world can still make death final.
+    fn check_death(&mut self) {
+        if self.status != GameStatus::Playing || self.player.hp > 0 {
+            return;
+        }
+        match self.player.checkpoint.clone() {
+            Some(node_id) => {
+                self.player.hp = self.player.max_hp;
+                self.player.statuses.clear();
+                self.player.enter(&node_id);
+                self.emit(GameEvent::Respawned(node_id));
+                self.note_seen_items();
+            }
+            None => {
+                let message = self.world.player.death_message.clone().unwrap_or_else(|| "You have died.".to_string());
+                self.status = GameStatus::Lost;
+                self.ending = Some(message.clone());
+                self.emit(GameEvent::GameEnded { status: GameStatus::Lost, message });
+            }
+        }
+    }
+
+    /// `use <feature>` without an item.
+    fn use_feature(&mut self, feature: Feature) {
+        let matching: Vec<&FeatureUse> = feature.on_use.iter().filter(|u| u.item.is_none()).collect();
+        let Some(rule) = matching.iter().find(|u| self.check_conditions(&u.conditions)) else {
+            if !matching.is_empty() {
+                self.emit(GameEvent::UseBlocked { item_id: feature.id, target: None });
+            } else {
+                self.emit(GameEvent::NothingHappens { item_id: feature.id, target: None });
+            }
+            return;
+        };
+
+        let used = GameEvent::FeatureUsed { feature_id: feature.id.clone(), message: rule.message.clone() };
+        let result = self.atomically(|state| {
+            state.emit(used);
+            state.apply_effects(&rule.effects)
+        });
+        match result {
+            Ok(()) => self.turn += 1,
+            Err(err) => self.emit(GameEvent::EffectFailed(err)),
+        }
+    }
+
+    fn combine(&mut self, item_ids: Vec<String>) {
+        let recipe = self.world.combinations.iter().enumerate().find(|(_, c)| c.is_named_by(&item_ids));
+        let Some((index, recipe)) = recipe.map(|(i, c)| (i, c.clone())) else {
+            return self.emit(GameEvent::CannotCombine(item_ids));
+        };
+
+        let needed = ItemStacks::from(recipe.ingredients.clone());
+        let reachable = self.reachable_items();
+        let missing: Vec<String> = needed
+            .iter()
+            .filter(|s| self.player.inventory.count(&s.item_id) < s.count)
+            .map(|s| s.item_id.clone())
+            .chain(recipe.tools.iter().filter(|t| !reachable.contains(t)).cloned())
+            .collect();
+        if !missing.is_empty() {
+            return self.emit(GameEvent::MissingIngredients(missing));
+        }
+        if !self.check_conditions(&recipe.conditions) {
+            return self.emit(GameEvent::CombineBlocked(item_ids));
+        }
+
+        // Only the units the recipe asks for are used up, and the results must fit
+        // once they are gone
+        let before = self.player.inventory.clone();
+        for stack in &needed {
+            self.player.inventory.remove(&stack.item_id, stack.count);
+        }
+        for stack in &recipe.results {
+            let incoming = ItemStacks::from(vec![stack.clone()]);
+            if let Err(event) = self.check_capacity(&stack.item_id, &incoming) {
+                self.player.inventory = before;
+                return self.emit(event);
+            }
+            self.player.inventory.append(incoming);
+        }
+        self.emit(GameEvent::Combined {
+            combination: index,
+            ingredients: needed,
+            results: ItemStacks::from(recipe.results),
+            message: recipe.message,
+        });
+        self.turn += 1;
+    }
+
+    fn node_mut(&mut self, node_id: &str) -> Result<&mut Node, EffectError> {
+        self.world.nodes.get_mut(node_id).ok_or_else(|| EffectError::UnknownNode(node_id.to_string()))
+    }
+
+    /// Applies an action, appends its rendered text to `log` and returns what happened.
+    pub fn apply_action(&mut self, action: GameAction) -> Vec<GameEvent> {
+        if self.is_over() && !matches!(action, GameAction::Help | GameAction::Look | GameAction::Inventory | GameAction::Map(_) | GameAction::Undo(_)) {
+            self.emit(GameEvent::GameIsOver);
+        } else {
+            self.perform_action(action);
+        }
+
+        self.render_events()
+    }
+
+    /// Takes the pending events and appends their lines to the log.
+    fn render_events(&mut self) -> Vec<GameEvent> {
+        let events = std::mem::take(&mut self.events);
+        for event in &events {
+            self.log.extend(EnglishRenderer.render(&self.world, event));
+        }
+        events
+    }
+
+    fn perform_action(&mut self, action: GameAction) {
+        let records_history = self.history_limit > 0 && !matches!(action, GameAction::Undo(_));
+        let before = records_history.then(|| (self.snapshot(), self.world.clone()));
+        let turn = self.turn;
+
+        match action {
+            GameAction::Help => self.emit(GameEvent::HelpShown),
             GameAction::Look => {
-                self.log.push(self.get_current_description());
                 if let Some(node) = self.world.nodes.get(&self.player.current_node) {
-                    if !node.items.is_empty() {
-                        let item_names: Vec<String> = node.items.iter()
-                            .map(|id| self.world.items.get(id).map(|i| i.name.clone()).unwrap_or(id.clone()))
-                            .collect();
-                        self.log.push(format!("Items here: {}", item_names.join(", ")));
-                    }
-                    let paths: Vec<String> = node.edges.iter().map(|e| e.label.clone()).collect();
-                    self.log.push(format!("Available paths: {}", paths.join(", ")));
-                }
-            }
-            GameAction::Move(target_id) => {
-                let current_node = self.world.nodes.get(&self.player.current_node).cloned();
-                if let Some(node) = current_node {
-                    if let Some(edge) = node.edges.iter().find(|e| e.target_id == target_id) {
-                        if self.can_traverse(edge) {
-                            self.player.current_node = target_id.clone();
-                            self.log.push(format!("You move to: {}.", edge.label));
-                            self.turn += 1;
-                        } else {
-                            self.log.push(format!("You cannot go to {}, conditions not met.", edge.label));
-                        }
-                    } else {
-                        self.log.push("You cannot go that way.".to_string());
-                    }
+                    let event = GameEvent::Looked {
+                        node_id: node.id.clone(),
+                        description: self.get_current_description(),
+                        items: node.items.clone(),
+                        npcs: self.npcs_here().iter().map(|n| n.id.clone()).collect(),
+                        paths: self.visible_edges().iter().map(|e| e.label.clone()).collect(),
+                    };
+                    self.emit(event);
                 }
             }
+            GameAction::Move(target_id) => self.move_to(target_id),
             GameAction::Explore(target) => {
                 if let Some(target_id) = target {
                     // Explore specific item or feature
-                    if let Some(item) = self.world.items.get(&target_id) {
-                        if self.player.inventory.contains(&target_id) || 
-                           self.world.nodes.get(&self.player.current_node).map_or(false, |n| n.items.contains(&target_id)) {
-                            self.log.push(format!("{}: {}", item.name, item.description));
-                        } else {
-                            self.log.push(format!("You don't see any {} here.", target_id));
+                    let visible = self.reachable_items().contains(&target_id);
+                    if self.world.items.contains_key(&target_id) && visible {
+                        self.emit(GameEvent::ItemExamined(target_id.clone()));
+                        if let Ok((_, container)) = self.open_container(&target_id, |_| true) {
+                            let contents = container.contents.clone();
+                            self.emit(GameEvent::ContainerSearched { container_id: target_id, contents });
+                        }
+                    } else if let Some(npc) = self.npcs_here().into_iter().find(|n| n.is_called(&target_id)) {
+                        self.emit(GameEvent::NpcExamined(npc.id.clone()));
+                    } else if let Some(feature) = self.find_feature(&target_id).cloned() {
+                        self.emit(GameEvent::FeatureExamined {
+                            feature_id: feature.id.clone(),
+                            description: feature.description.clone(),
+                        });
+                        for name in std::iter::once(&feature.id).chain(&feature.aliases) {
+                            self.reveal_secrets(Some(name));
                         }
+                    } else if self.reveal_secrets(Some(&target_id)) > 0 {
+                        // The discoveries describe themselves
+                    } else if self.world.items.contains_key(&target_id) {
+                        self.emit(GameEvent::ItemNotVisible(target_id));
                     } else {
-                        self.log.push(format!("You don't see anything special about {}.", target_id));
+                        self.emit(GameEvent::NothingSpecial(target_id));
                     }
-                } else {
-                    self.log.push("You look around carefully but find nothing new.".to_string());
+                } else if self.reveal_secrets(None) == 0 {
+                    self.emit(GameEvent::NothingFound);
                 }
                 self.turn += 1;
             }
-            GameAction::Pickup(item_id) => {
+            GameAction::Pickup(item_id, count) => {
                 let current_node_id = self.player.current_node.clone();
-                if let Some(node) = self.world.nodes.get_mut(&current_node_id) {
-                    if let Some(pos) = node.items.iter().position(|id| id == &item_id) {
-                        let can_pickup = self.world.items.get(&item_id).map_or(true, |i| i.can_pickup);
-                        if can_pickup {
-                            node.items.remove(pos);
-                            self.player.inventory.push(item_id.clone());
-                            let name = self.world.items.get(&item_id).map_or(item_id.clone(), |i| i.name.clone());
-                            self.log.push(format!("You picked up: {}", name));
-                            self.turn += 1;
+                if let Some(node) = self.world.nodes.get(&current_node_id) {
+                    let available = node.items.count(&item_id);
+                    if available > 0 && count.is_some_and(|count| count > available) {
+                        self.emit(GameEvent::NotEnough { item_id, available });
+                    } else if available > 0 {
+                        let count = count.unwrap_or(available);
+                        let taken = node.items.clone().take(&item_id, count).unwrap_or_default();
+                        let can_pickup = self.world.items.get(&item_id).is_none_or(|i| i.can_pickup);
+                        if !can_pickup {
+                            self.emit(GameEvent::CannotPickUp(item_id));
+                        } else if let Err(event) = self.check_capacity(&item_id, &taken) {
+                            self.emit(event);
                         } else {
-                            self.log.push("You cannot pick that up.".to_string());
+                            let effects = self.world.items.get(&item_id).map(|i| i.on_pickup.clone()).unwrap_or_default();
+                            let result = self.atomically(|state| {
+                                state.node_mut(&current_node_id)?.items.take(&item_id, count);
+                                state.player.inventory.append(taken);
+                                state.emit(GameEvent::ItemPickedUp(item_id.clone(), count));
+                                state.apply_effects(&effects)
+                            });
+                            match result {
+                                Ok(()) => self.turn += 1,
+                                Err(err) => self.emit(GameEvent::EffectFailed(err)),
+                            }
                         }
                     } else {
-                        self.log.push("That item is not here.".to_string());
+                        self.emit(GameEvent::ItemNotHere(item_id));
                     }
                 }
             }
-            GameAction::Drop(item_id) => {
-                if let Some(pos) = self.player.inventory.iter().position(|id| id == &item_id) {
-                    self.player.inventory.remove(pos);
+            GameAction::Drop(item_id, count) => {
+                let available = self.player.inventory.count(&item_id);
+                if available > 0 && count.is_some_and(|count| count > available) {
+                    self.emit(GameEvent::NotEnough { item_id, available });
+                } else if available > 0 {
+                    let count = count.unwrap_or(available);
+                    let dropped = self.player.inventory.take(&item_id, count).unwrap_or_default();
                     if let Some(node) = self.world.nodes.get_mut(&self.player.current_node) {
-                        node.items.push(item_id.clone());
+                        node.items.append(dropped);
                     }
-                    let name = self.world.items.get(&item_id).map_or(item_id.clone(), |i| i.name.clone());
-                    self.log.push(format!("You dropped: {}", name));
+                    self.emit(GameEvent::ItemDropped(item_id, count));
                     self.turn += 1;
                 } else {
-                    self.log.push("You don't have that item.".to_string());
+                    self.emit(GameEvent::NotCarrying(item_id));
                 }
             }
             GameAction::Inventory => {
-                if self.player.inventory.is_empty() {
-                    self.log.push("Your inventory is empty.".to_string());
-                } else {
-                    let item_names: Vec<String> = self.player.inventory.iter()
-                        .map(|id| self.world.items.get(id).map(|i| i.name.clone()).unwrap_or(id.clone()))
-                        .collect();
-                    self.log.push(format!("You are carrying: {}", item_names.join(", ")));
-                }
+                self.emit(GameEvent::InventoryShown(self.player.inventory.clone()));
+            }
+            GameAction::Map(fog_of_war) => {
+                self.emit(GameEvent::MapShown(self.get_ascii_map(fog_of_war)));
             }
-            GameAction::Use(item_id) => {
+            GameAction::Open(container_id) => self.open(container_id),
+            GameAction::Talk(npc_id) => self.talk(npc_id),
+            GameAction::Say(number) => self.say(number),
+            GameAction::Attack(npc_id, weapon) => self.attack(npc_id, weapon),
+            GameAction::Flee => self.flee(),
+            GameAction::Close(container_id) => self.close(container_id),
+            GameAction::PutIn(item_id, container_id) => self.put_in(item_id, container_id),
+            GameAction::TakeFrom(item_id, container_id) => self.take_from(item_id, container_id),
+            GameAction::Use(item_id, target) => {
                 if self.player.inventory.contains(&item_id) {
-                    self.log.push(format!("You use the {}. Nothing obvious happens.", item_id));
-                    self.turn += 1;
+                    self.use_item(&item_id, target.as_deref());
+                } else if let (Some(feature), None) = (self.find_feature(&item_id).cloned(), &target) {
+                    self.use_feature(feature);
                 } else {
-                    self.log.push("You don't have that item.".to_string());
+                    self.emit(GameEvent::NotCarrying(item_id));
                 }
             }
-            GameAction::Combine(item1, item2) => {
-                if self.player.inventory.contains(&item1) && self.player.inventory.contains(&item2) {
-                    let combination = self.world.combinations.iter().find(|c| 
-                        (c.item1 == item1 && c.item2 == item2) || (c.item1 == item2 && c.item2 == item1)
-                    ).cloned();
-
-                    if let Some(combo) = combination {
-                        self.player.inventory.retain(|id| id != &item1 && id != &item2);
-                        self.player.inventory.push(combo.result.clone());
-                        let result_name = self.world.items.get(&combo.result).map_or(combo.result.clone(), |i| i.name.clone());
-                        self.log.push(format!("You combined them and created: {}!", result_name));
-                        self.turn += 1;
-                    } else {
-                        self.log.push("Those items cannot be combined.".to_string());
-                    }
-                } else {
-                    self.log.push("You need both items in your inventory to combine them.".to_string());
-                }
+            GameAction::Combine(item_ids) => self.combine(item_ids),
+            GameAction::Undo(steps) => {
+                self.undo(steps);
             }
             GameAction::Invalid(cmd) => {
-                self.log.push(format!("Unknown command: {}", cmd));
+                self.emit(GameEvent::UnknownCommand(cmd));
+            }
+        }
+
+        if self.turn > turn && self.status == GameStatus::Playing {
+            self.hostiles_strike();
+            self.tick_statuses();
+        }
+        self.note_seen_items();
+        self.check_endings();
+        self.check_death();
+
+        if let Some((mut snapshot, world)) = before {
+            if snapshot.turn != self.turn || snapshot.status != self.status {
+                snapshot.keep_changes(world, &self.world);
+                self.push_history(snapshot);
+            } else if let Some(last) = self.history.last_mut() {
+                // Not a turn of its own, so undoing the last turn takes this back too
+                last.keep_changes(world, &self.world);
+            }
+        }
+    }
+
+    /// A snapshot of the current turn that keeps none of the world yet.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            player: self.player.clone(),
+            nodes: HashMap::new(),
+            items: HashMap::new(),
+            npcs: HashMap::new(),
+            flags: HashMap::new(),
+            turn: self.turn,
+            status: self.status.clone(),
+            ending: self.ending.clone(),
+            rng: self.rng,
+        }
+    }
+
+    fn push_history(&mut self, snapshot: Snapshot) {
+        self.history.push(snapshot);
+        if self.history.len() > self.history_limit {
+            let excess = self.history.len() - self.history_limit;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Rewinds up to `steps` turns. Returns the number of turns actually undone.
+    fn undo(&mut self, steps: usize) -> usize {
+        let steps = steps.min(self.history.len());
+        if steps == 0 {
+            self.emit(GameEvent::NothingToUndo);
+            return 0;
+        }
+
+        let undone = self.history.split_off(self.history.len() - steps);
+        for snapshot in undone.into_iter().rev() {
+            self.restore(snapshot);
+        }
+        self.emit(GameEvent::Undone(steps));
+        steps
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.player = snapshot.player;
+        self.world.nodes.extend(snapshot.nodes);
+        self.world.items.extend(snapshot.items);
+        self.world.npcs.extend(snapshot.npcs);
+        for (key, value) in snapshot.flags {
+            match value {
+                Some(value) => self.world.flags.insert(key, value),
+                None => self.world.flags.remove(&key),
+            };
+        }
+        self.turn = snapshot.turn;
+        self.status = snapshot.status;
+        self.ending = snapshot.ending;
+        self.rng = snapshot.rng;
+    }
+
+    /// Finishes the run if any defeat or victory ending applies. Defeat is checked
+    /// first, so losing and winning on the same turn counts as a loss.
+    fn check_endings(&mut self) {
+        if self.status != GameStatus::Playing {
+            return;
+        }
+        let defeat = self.world.defeat.iter().find(|e| self.check_conditions(&e.conditions));
+        let victory = self.world.victory.iter().find(|e| self.check_conditions(&e.conditions));
+        let (status, ending) = match (defeat, victory) {
+            (Some(ending), _) => (GameStatus::Lost, ending.message.clone()),
+            (None, Some(ending)) => (GameStatus::Won, ending.message.clone()),
+            (None, None) => return,
+        };
+        self.status = status.clone();
+        self.ending = Some(ending.clone());
+        self.emit(GameEvent::GameEnded { status, message: ending });
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.status != GameStatus::Playing
+    }
+
+    /// The item id meant by `name`: the id or the item name, either in the plural
+    /// (`coins` for `coin`), or just its last word when only one item ends in it
+    /// (`coin` for `gold_coin`). Unknown names are returned unchanged. Every command
+    /// argument that names an item goes through here.
+    pub fn resolve_item_name(&self, name: &str) -> String {
+        let mut items: Vec<&Item> = self.world.items.values().collect();
+        items.sort_by(|a, b| a.id.cmp(&b.id));
+        let singulars = [Some(name), name.strip_suffix('s'), name.strip_suffix("es")];
+        for candidate in singulars.into_iter().flatten() {
+            let candidate = candidate.replace(' ', "_");
+            if let Some(item) = items
+                .iter()
+                .find(|i| i.id.eq_ignore_ascii_case(&candidate) || i.name.replace(' ', "_").eq_ignore_ascii_case(&candidate))
+            {
+                return item.id.clone();
+            }
+            let suffix = format!("_{}", candidate.to_lowercase());
+            let endings: Vec<&&Item> = items.iter().filter(|i| i.id.to_lowercase().ends_with(&suffix)).collect();
+            if let [item] = endings.as_slice() {
+                return item.id.clone();
             }
         }
+        name.to_string()
+    }
+
+    /// Like `resolve_item_name`, for arguments that may also name a feature or NPC
+    /// here, or a node: those keep their name.
+    fn resolve_target_name(&self, name: &str) -> String {
+        if self.find_feature(name).is_some()
+            || self.npcs_here().iter().any(|n| n.is_called(name))
+            || self.world.nodes.contains_key(name)
+        {
+            return name.to_string();
+        }
+        self.resolve_item_name(name)
+    }
+
+    /// Splits `2 coins` into the item id and a count; no leading number means the
+    /// whole stack. `None` for a count of zero.
+    fn parse_quantity(&self, args: &[&str]) -> Option<(String, Option<u32>)> {
+        match args.first().map(|a| a.parse::<u32>()) {
+            Some(Ok(0)) => None,
+            Some(Ok(count)) if args.len() > 1 => Some((self.resolve_item_name(&args[1..].join(" ")), Some(count))),
+            _ => Some((self.resolve_item_name(&args.join(" ")), None)),
+        }
     }
 
     pub fn parse_command(&self, input: &str) -> GameAction {
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        let parts: Vec<&str> = input.split_whitespace().collect();
         if parts.is_empty() {
             return GameAction::Invalid("".to_string());
         }
@@ -397,36 +2278,126 @@ impl GameState {
                 if args.is_empty() {
                     GameAction::Explore(None)
                 } else {
-                    GameAction::Explore(Some(args.join(" ")))
+                    GameAction::Explore(Some(self.resolve_target_name(&args.join(" "))))
                 }
             }
             "p" | "pickup" | "get" | "take" => {
                 if args.is_empty() {
                     GameAction::Invalid("Pick up what?".to_string())
+                } else if let Some(pos) = args.iter().position(|a| a.eq_ignore_ascii_case("from")) {
+                    if pos == 0 || pos == args.len() - 1 {
+                        GameAction::Invalid("Take what from what?".to_string())
+                    } else {
+                        GameAction::TakeFrom(
+                            self.resolve_item_name(&args[..pos].join(" ")),
+                            self.resolve_item_name(&args[pos + 1..].join(" ")),
+                        )
+                    }
+                } else {
+                    match self.parse_quantity(args) {
+                        Some((item_id, count)) => GameAction::Pickup(item_id, count),
+                        None => GameAction::Invalid("Pick up how many?".to_string()),
+                    }
+                }
+            }
+            "d" | "drop" => {
+                if args.is_empty() {
+                    GameAction::Invalid("Drop what?".to_string())
+                } else {
+                    match self.parse_quantity(args) {
+                        Some((item_id, count)) => GameAction::Drop(item_id, count),
+                        None => GameAction::Invalid("Drop how many?".to_string()),
+                    }
+                }
+            }
+            "put" => match args.iter().position(|a| a.eq_ignore_ascii_case("in") || a.eq_ignore_ascii_case("into")) {
+                Some(pos) if pos > 0 && pos < args.len() - 1 => {
+                    GameAction::PutIn(
+                        self.resolve_item_name(&args[..pos].join(" ")),
+                        self.resolve_item_name(&args[pos + 1..].join(" ")),
+                    )
+                }
+                _ => GameAction::Invalid("Put what in what?".to_string()),
+            },
+            "open" => {
+                if args.is_empty() {
+                    GameAction::Invalid("Open what?".to_string())
+                } else {
+                    GameAction::Open(self.resolve_item_name(&args.join(" ")))
+                }
+            }
+            "close" | "shut" => {
+                if args.is_empty() {
+                    GameAction::Invalid("Close what?".to_string())
                 } else {
-                    GameAction::Pickup(args.join(" "))
+                    GameAction::Close(self.resolve_item_name(&args.join(" ")))
                 }
             }
-            "d" | "drop" => {
-                if args.is_empty() {
-                    GameAction::Invalid("Drop what?".to_string())
+            "t" | "talk" | "speak" => match args {
+                [] | ["to" | "with"] => GameAction::Invalid("Talk to whom?".to_string()),
+                ["to" | "with", name @ ..] | name => GameAction::Talk(self.resolve_npc_name(&name.join(" "))),
+            },
+            "say" => match args.first().map(|a| a.parse::<usize>()) {
+                Some(Ok(number)) => GameAction::Say(number),
+                _ => GameAction::Invalid("Say which choice? Give its number.".to_string()),
+            },
+            "attack" | "fight" | "hit" | "kill" => {
+                let (target, weapon) = match args.iter().position(|a| a.eq_ignore_ascii_case("with")) {
+                    Some(pos) => (&args[..pos], Some(&args[pos + 1..])),
+                    None => (args, None),
+                };
+                if target.is_empty() || weapon.is_some_and(|w| w.is_empty()) {
+                    GameAction::Invalid("Attack what?".to_string())
                 } else {
-                    GameAction::Drop(args.join(" "))
+                    let weapon = weapon.map(|w| self.resolve_item_name(&w.join(" ")));
+                    GameAction::Attack(self.resolve_npc_name(&target.join(" ")), weapon)
                 }
             }
+            "flee" | "run" | "retreat" => GameAction::Flee,
+            // While talking, a bare number picks a choice
+            number if self.player.conversation.is_some() && number.parse::<usize>().is_ok() => {
+                GameAction::Say(number.parse().unwrap_or_default())
+            }
             "i" | "inventory" | "inv" => GameAction::Inventory,
+            "m" | "map" => GameAction::Map(!args.first().is_some_and(|a| a.eq_ignore_ascii_case("all"))),
+            "undo" | "rewind" => match args.first() {
+                None => GameAction::Undo(1),
+                Some(n) => match n.parse::<usize>() {
+                    Ok(steps) if steps > 0 => GameAction::Undo(steps),
+                    _ => GameAction::Invalid("Undo how many turns?".to_string()),
+                },
+            },
             "u" | "use" => {
                 if args.is_empty() {
                     GameAction::Invalid("Use what?".to_string())
+                } else if let Some(pos) = args.iter().position(|a| a.eq_ignore_ascii_case("on") || a.eq_ignore_ascii_case("with")) {
+                    if pos == 0 || pos == args.len() - 1 {
+                        GameAction::Invalid("Use what on what?".to_string())
+                    } else {
+                        GameAction::Use(
+                            self.resolve_target_name(&args[..pos].join(" ")),
+                            Some(self.resolve_target_name(&args[pos + 1..].join(" "))),
+                        )
+                    }
                 } else {
-                    GameAction::Use(args.join(" "))
+                    GameAction::Use(self.resolve_target_name(&args.join(" ")), None)
                 }
             }
             "c" | "combine" => {
-                if args.len() < 2 {
+                // `wild herbs with empty bottle` names two items, `ore coal hammer` three
+                let mut names = Vec::new();
+                for group in args.split(|a| ["with", "and", "+"].iter().any(|w| a.eq_ignore_ascii_case(w))) {
+                    let whole = self.resolve_item_name(&group.join(" "));
+                    if group.len() > 1 && !self.world.items.contains_key(&whole) {
+                        names.extend(group.iter().map(|a| self.resolve_item_name(a)));
+                    } else if !group.is_empty() {
+                        names.push(whole);
+                    }
+                }
+                if names.is_empty() {
                     GameAction::Invalid("Combine what with what?".to_string())
                 } else {
-                    GameAction::Combine(args[0].to_string(), args[1].to_string())
+                    GameAction::Combine(names)
                 }
             }
             _ => {
@@ -476,12 +2447,28 @@ mod tests {
             description: "A dark cave".to_string(),
             attributes: HashMap::new(),
             edges: vec![],
-            items: vec![],
+            items: ItemStacks::new(),
+            hidden_items: vec![],
+            features: HashMap::new(),
+            first_visit_description: None,
+            description_fragments: vec![],
+            on_enter: vec![],
+            on_first_enter: vec![],
+            on_exit: vec![],
+            position: None,
+            hazard: None,
+            checkpoint: false,
         });
         let world = World {
             nodes,
             items: HashMap::new(),
             combinations: vec![],
+            npcs: HashMap::new(),
+            tables: HashMap::new(),
+            flags: HashMap::new(),
+            victory: vec![],
+            defeat: vec![],
+            player: PlayerConfig::default(),
         };
         
         let template = WorldTemplate::from_world(&world);
@@ -493,167 +2480,1388 @@ mod tests {
     }
 
     #[test]
-    fn test_complex_interactions() {
+    fn test_complex_interactions() {
+        let mut state = GameState::new("Tester".to_string());
+        
+        // Add items and combination to world
+        state.world.items.insert("stick".to_string(), Item {
+            id: "stick".to_string(),
+            name: "Stick".to_string(),
+            description: "A stick".to_string(),
+            can_pickup: true,
+            on_pickup: vec![],
+            use_rules: vec![],
+            container: None,
+            weight: 0,
+            bulk: 1,
+            state: HashMap::new(),
+            heals: 0,
+            cures: vec![],
+            attack: 0,
+            defense: 0,
+        });
+        state.world.items.insert("stone".to_string(), Item {
+            id: "stone".to_string(),
+            name: "Stone".to_string(),
+            description: "A stone".to_string(),
+            can_pickup: true,
+            on_pickup: vec![],
+            use_rules: vec![],
+            container: None,
+            weight: 0,
+            bulk: 1,
+            state: HashMap::new(),
+            heals: 0,
+            cures: vec![],
+            attack: 0,
+            defense: 0,
+        });
+        state.world.items.insert("torch".to_string(), Item {
+            id: "torch".to_string(),
+            name: "Torch".to_string(),
+            description: "A torch".to_string(),
+            can_pickup: true,
+            on_pickup: vec![],
+            use_rules: vec![],
+            container: None,
+            weight: 0,
+            bulk: 1,
+            state: HashMap::new(),
+            heals: 0,
+            cures: vec![],
+            attack: 0,
+            defense: 0,
+        });
+        state.world.combinations.push(Combination {
+            ingredients: vec![ItemStack::new("stick", 1), ItemStack::new("stone", 1)],
+            tools: vec![],
+            results: vec![ItemStack::new("torch", 1)],
+            conditions: vec![],
+            message: None,
+        });
+        
+        // Put items in current node
+        state.world.nodes.get_mut("start").unwrap().items.add("stick", 1);
+        state.world.nodes.get_mut("start").unwrap().items.add("stone", 1);
+        
+        // Pickup items
+        state.apply_action(GameAction::Pickup("stick".to_string(), None));
+        state.apply_action(GameAction::Pickup("stone".to_string(), None));
+        assert!(state.player.inventory.contains("stick"));
+        assert!(state.player.inventory.contains("stone"));
+        
+        // Combine items
+        state.apply_action(GameAction::Combine(vec!["stick".to_string(), "stone".to_string()]));
+        assert!(!state.player.inventory.contains("stick"));
+        assert!(!state.player.inventory.contains("stone"));
+        assert!(state.player.inventory.contains("torch"));
+        
+        // Use torch to unlock path (simulation of conditions)
+        let edge = Edge {
+            target_id: "cave".to_string(),
+            label: "Cave".to_string(),
+            conditions: vec![Condition {
+                condition_type: ConditionType::HasItem("torch".to_string()),
+            }],
+            effects: vec![],
+            hidden: None,
+            hazard: None,
+        };
+        assert!(state.can_traverse(&edge));
+    }
+
+    #[test]
+    fn test_yaml_deserialization() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "Start"
+    attributes: {}
+    edges:
+      - target_id: forest
+        label: "Forest"
+        conditions: []
+    items: []
+  forest:
+    id: forest
+    description: "Forest"
+    attributes: {}
+    edges:
+      - target_id: cave
+        label: "Cave"
+        conditions:
+          - !HasItem torch
+    items: []
+items:
+  torch:
+    id: torch
+    name: "Torch"
+    description: "Torch"
+    can_pickup: true
+combinations: []
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let forest = template.nodes.get("forest").unwrap();
+        assert_eq!(forest.edges[0].conditions.len(), 1);
+        match &forest.edges[0].conditions[0].condition_type {
+            ConditionType::HasItem(item) => assert_eq!(item, "torch"),
+            _ => panic!("Expected HasItem condition"),
+        }
+    }
+
+    #[test]
+    fn test_whispering_woods_walkthrough() {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        let mut state = GameState::new_with_world("Hero".to_string(), template.to_world());
+
+        // 1. Start at the Forest Crossroads. Pick up the flint.
+        assert_eq!(state.player.current_node, "start");
+        state.apply_action(GameAction::Pickup("flint".to_string(), None));
+        assert!(state.player.inventory.contains("flint"));
+
+        // 2. Go to the Stone Bridge and pick up the dry_wood.
+        state.apply_action(GameAction::Move("bridge".to_string()));
+        assert_eq!(state.player.current_node, "bridge");
+        state.apply_action(GameAction::Pickup("dry_wood".to_string(), None));
+        assert!(state.player.inventory.contains("dry_wood"));
+
+        // 3. Combine flint and dry_wood to create a torch.
+        state.apply_action(GameAction::Combine(vec!["flint".to_string(), "dry_wood".to_string()]));
+        assert!(state.player.inventory.contains("torch"));
+        assert!(!state.player.inventory.contains("flint"));
+        assert!(!state.player.inventory.contains("dry_wood"));
+
+        // 4. Go to the Forgotten Path, pick up wild_herbs, and use the torch to enter the Echoing Cave.
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Move("forgotten_path".to_string()));
+        assert_eq!(state.player.current_node, "forgotten_path");
+        state.apply_action(GameAction::Pickup("wild_herbs".to_string(), None));
+        assert!(state.player.inventory.contains("wild_herbs"));
+
+        state.apply_action(GameAction::Move("cave".to_string()));
+        assert_eq!(state.player.current_node, "cave");
+
+        // 5. Find the iron_key inside the cave.
+        state.apply_action(GameAction::Pickup("iron_key".to_string(), None));
+        assert!(state.player.inventory.contains("iron_key"));
+
+        // 6. Go to the Hut Exterior and use the iron_key to enter the Hut Interior.
+        state.apply_action(GameAction::Move("forgotten_path".to_string()));
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Move("hut_exterior".to_string()));
+        assert_eq!(state.player.current_node, "hut_exterior");
+
+        state.apply_action(GameAction::Move("hut_interior".to_string()));
+        assert_eq!(state.player.current_node, "hut_exterior");
+        state.apply_action(state.parse_command("use iron_key on door"));
+        state.apply_action(GameAction::Move("hut_interior".to_string()));
+        assert_eq!(state.player.current_node, "hut_interior");
+
+        // 7. Pick up the cauldron and combine it with wild_herbs to brew the purifying_potion.
+        state.apply_action(GameAction::Pickup("cauldron".to_string(), None));
+        assert!(state.player.inventory.contains("cauldron"));
+
+        state.apply_action(GameAction::Combine(vec!["wild_herbs".to_string(), "cauldron".to_string()]));
+        assert!(state.player.inventory.contains("purifying_potion"));
+
+        // 8. Travel through the Stone Bridge to the Castle Gate.
+        state.apply_action(GameAction::Move("hut_exterior".to_string()));
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Move("bridge".to_string()));
+        state.apply_action(GameAction::Move("castle_gate".to_string()));
+        assert_eq!(state.player.current_node, "castle_gate");
+
+        // 9. Use the purifying_potion to break the seal and enter the Castle Keep.
+        state.apply_action(GameAction::Move("castle_keep".to_string()));
+        assert_eq!(state.player.current_node, "castle_gate");
+        state.apply_action(state.parse_command("use purifying_potion with barrier"));
+        assert!(!state.player.inventory.contains("purifying_potion"));
+        state.apply_action(GameAction::Move("castle_keep".to_string()));
+        assert_eq!(state.player.current_node, "castle_keep");
+
+        // 10. Retrieve the artifact and lift the curse.
+        state.apply_action(GameAction::Pickup("artifact".to_string(), None));
+        assert!(state.player.inventory.contains("artifact"));
+        assert_eq!(state.status, GameStatus::Won);
+    }
+
+    #[test]
+    fn test_composite_conditions() {
+        let yaml = r#"
+- !Any
+  - !HasItem torch
+  - !HasItem lantern
+- !Not
+  - !HasAttribute [cursed, "true"]
+- !All
+  - !MinHP 10
+  - !Any [!HasItem map, !Not [!HasItem blindfold]]
+"#;
+        let conditions: Vec<Condition> = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(conditions.len(), 3);
+        assert!(matches!(&conditions[0].condition_type, ConditionType::Any(c) if c.len() == 2));
+
+        let mut state = GameState::new("Tester".to_string());
+        assert!(!state.check_conditions(&conditions));
+
+        state.player.inventory.add("lantern", 1);
+        assert!(state.check_conditions(&conditions));
+
+        state.player.attributes.insert("cursed".to_string(), "true".to_string());
+        assert!(!state.check_conditions(&conditions));
+
+        state.player.attributes.remove("cursed");
+        state.player.inventory.add("blindfold", 1);
+        assert!(!state.check_conditions(&conditions));
+
+        let edge = Edge {
+            target_id: "forest".to_string(),
+            label: "Forest".to_string(),
+            conditions: conditions.clone(),
+            effects: vec![],
+            hidden: None,
+            hazard: None,
+        };
+        state.player.inventory.add("map", 1);
+        assert!(state.can_traverse(&edge));
+
+        let yaml = serde_yaml::to_string(&conditions).unwrap();
+        let round_trip: Vec<Condition> = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(round_trip, conditions);
+
+        // Not wraps exactly one condition; an empty list or a NAND is an error
+        assert!(serde_yaml::from_str::<Vec<Condition>>("- !Not []").is_err());
+        assert!(serde_yaml::from_str::<Vec<Condition>>("- !Not [!HasItem map, !HasItem torch]").is_err());
+    }
+
+    #[test]
+    fn test_yaml_predicate_conditions() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "Start"
+    attributes: {}
+    edges:
+      - target_id: arena
+        label: "Arena"
+        conditions:
+          - !AttributeCompare [strength, ">=", 3]
+          - !MaxHP 50
+      - target_id: tower
+        label: "Tower"
+        conditions:
+          - !TurnAtLeast 1
+          - !TurnBefore 3
+          - !HasVisited arena
+      - target_id: vault
+        label: "Vault"
+        conditions:
+          - !ItemAt [gem, arena]
+          - !NodeAttribute [arena, gate, open]
+          - !WorldFlag [alarm, "off"]
+    items: []
+  arena:
+    id: arena
+    description: "Arena"
+    attributes:
+      gate: open
+    edges:
+      - target_id: start
+        label: "Back"
+        conditions: []
+    items:
+      - gem
+  tower:
+    id: tower
+    description: "Tower"
+    attributes: {}
+    edges: []
+    items: []
+  vault:
+    id: vault
+    description: "Vault"
+    attributes: {}
+    edges: []
+    items: []
+flags:
+  alarm: "off"
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let start = template.nodes.get("start").unwrap();
+        assert_eq!(
+            start.edges[0].conditions[0].condition_type,
+            ConditionType::AttributeCompare("strength".to_string(), Comparison::Ge, 3)
+        );
+
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        let edges = state.world.nodes.get("start").unwrap().edges.clone();
+
+        // Numeric attribute comparison and HP cap
+        assert!(!state.can_traverse(&edges[0]));
+        state.player.attributes.insert("strength".to_string(), "2".to_string());
+        assert!(!state.can_traverse(&edges[0]));
+        state.player.attributes.insert("strength".to_string(), "3".to_string());
+        assert!(!state.can_traverse(&edges[0]));
+        state.player.hp = 50;
+        assert!(state.can_traverse(&edges[0]));
+
+        // Turn window and visited nodes
+        assert!(!state.can_traverse(&edges[1]));
+        state.apply_action(GameAction::Move("arena".to_string()));
+        state.apply_action(GameAction::Move("start".to_string()));
+        assert_eq!(state.player.visits.get("start"), Some(&2));
+        assert!(state.can_traverse(&edges[1]));
+        state.turn = 3;
+        assert!(!state.can_traverse(&edges[1]));
+
+        // World-side predicates
+        assert!(state.can_traverse(&edges[2]));
+        state.world.flags.insert("alarm".to_string(), "on".to_string());
+        assert!(!state.can_traverse(&edges[2]));
+        state.world.flags.insert("alarm".to_string(), "off".to_string());
+        state.world.nodes.get_mut("arena").unwrap().items = ItemStacks::new();
+        assert!(!state.can_traverse(&edges[2]));
+    }
+
+    #[test]
+    fn test_effects() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "Start"
+    attributes: {}
+    edges:
+      - target_id: shrine
+        label: "Shrine"
+        conditions: []
+        effects:
+          - !SetAttribute [blessed, "true"]
+          - !AdjustHP -30
+          - !Message "A cold wind passes through you."
+      - target_id: gate
+        label: "Gate"
+        conditions: []
+        effects:
+          - !RemoveItem key
+          - !SetFlag [gate, open]
+    items: [coin]
+  shrine:
+    id: shrine
+    description: "Shrine"
+    attributes: {}
+    edges: []
+    items: []
+    on_enter:
+      - !SpawnItem [coin, start]
+      - !AddEdge [shrine, {target_id: start, label: "Back", conditions: []}]
+      - !SetDescription [shrine, "An empty shrine."]
+  gate:
+    id: gate
+    description: "Gate"
+    attributes: {}
+    edges: []
+    items: []
+    on_enter:
+      - !EndGame [Won, "You made it through the gate!"]
+items:
+  coin:
+    id: coin
+    name: "Coin"
+    description: "A coin"
+    can_pickup: true
+    on_pickup:
+      - !GiveItem key
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+
+        // A failing effect rolls back the whole move
+        state.apply_action(GameAction::Move("gate".to_string()));
+        assert_eq!(state.player.current_node, "start");
+        assert_eq!(state.turn, 0);
+        assert!(state.world.flags.is_empty());
+
+        let events = state.apply_action(GameAction::Move("shrine".to_string()));
+        assert_eq!(state.player.current_node, "shrine");
+        assert!(events.contains(&GameEvent::ItemSpawned { item_id: "coin".to_string(), node_id: "start".to_string() }));
+        assert!(events.contains(&GameEvent::EdgeAdded { node_id: "shrine".to_string(), target_id: "start".to_string() }));
+        assert!(events.contains(&GameEvent::DescriptionChanged("shrine".to_string())));
+        assert_eq!(state.player.attributes.get("blessed"), Some(&"true".to_string()));
+        assert_eq!(state.player.hp, 70);
+        assert_eq!(state.log.last(), Some(&"A cold wind passes through you.".to_string()));
+        assert_eq!(state.get_current_description(), "An empty shrine.");
+        assert_eq!(state.world.nodes.get("start").unwrap().items.count("coin"), 2);
+
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Pickup("coin".to_string(), None));
+        assert!(state.player.inventory.contains("key"));
+
+        state.apply_action(GameAction::Move("gate".to_string()));
+        assert_eq!(state.player.current_node, "gate");
+        assert!(!state.player.inventory.contains("key"));
+        assert_eq!(state.world.flags.get("gate"), Some(&"open".to_string()));
+        assert_eq!(state.status, GameStatus::Won);
+
+        let turn = state.turn;
+        state.apply_action(GameAction::Drop("coin".to_string(), None));
+        assert_eq!(state.turn, turn);
+        assert!(state.player.inventory.contains("coin"));
+    }
+
+    #[test]
+    fn test_use_rules() {
+        let mut state = GameState::new("Tester".to_string());
+        let yaml = r#"
+id: potion
+name: "Healing Potion"
+description: "A red potion"
+can_pickup: true
+use_rules:
+  - target: !Item wound_kit
+    message: "You mix the potion into the kit."
+    effects:
+      - !SetAttribute [kit, mixed]
+  - target: !Feature altar
+    conditions:
+      - !AtNode forest
+    consume: true
+    message: "You pour the potion on the altar."
+    effects:
+      - !SetFlag [altar, blessed]
+  - consume: true
+    effects:
+      - !AdjustHP 20
+"#;
+        let potion: Item = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(potion.use_rules[1].target, Some(UseTarget::Feature("altar".to_string())));
+        state.world.items.insert("potion".to_string(), potion);
+        state.player.inventory.add("potion", 1);
+        state.player.hp = 50;
+
+        assert!(matches!(state.parse_command("use potion with wound_kit"), GameAction::Use(i, Some(t)) if i == "potion" && t == "wound_kit"));
+        assert!(matches!(state.parse_command("use potion"), GameAction::Use(i, None) if i == "potion"));
+        assert!(matches!(state.parse_command("use on altar"), GameAction::Invalid(_)));
+
+        // Target item is not around
+        state.apply_action(state.parse_command("use potion on wound_kit"));
+        assert!(!state.player.attributes.contains_key("kit"));
+        assert_eq!(state.turn, 0);
+
+        // Feature rule is gated by location
+        state.apply_action(state.parse_command("use potion on altar"));
+        assert!(state.world.flags.is_empty());
+        assert_eq!(state.log.last(), Some(&"You can't use the Healing Potion like that yet.".to_string()));
+
+        state.apply_action(GameAction::Move("forest".to_string()));
+        state.apply_action(state.parse_command("use potion on altar"));
+        assert_eq!(state.world.flags.get("altar"), Some(&"blessed".to_string()));
+        assert!(!state.player.inventory.contains("potion"));
+
+        state.player.inventory.add("potion", 1);
+        state.apply_action(state.parse_command("use potion"));
+        assert_eq!(state.player.hp, 70);
+        assert!(state.player.inventory.is_empty());
+    }
+
+    #[test]
+    fn test_node_triggers_and_descriptions() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A quiet square."
+    attributes: {}
+    edges:
+      - target_id: hall
+        label: "Hall"
+        conditions: []
+    items: []
+    on_first_enter:
+      - !Message "Pigeons scatter as you arrive."
+    on_exit:
+      - effects:
+          - !Message "The fountain falls silent behind you."
+  hall:
+    id: hall
+    description: "The hall."
+    first_visit_description: "You step into a vast hall for the first time."
+    description_fragments:
+      - conditions:
+          - !ItemAt [banner, hall]
+        text: "A banner hangs on the wall."
+      - conditions:
+          - !Not [!ItemAt [banner, hall]]
+        text: "A bare hook juts from the wall."
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Square"
+        conditions: []
+    items: [banner]
+    on_first_enter:
+      - effects:
+          - !Message "Trumpets sound!"
+          - !SetAttribute [herald, "true"]
+    on_enter:
+      - !AdjustHP -1
+      - conditions:
+          - !HasItem banner
+        effects:
+          - !Message "The guards salute the banner."
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        // A bare effect reads as a trigger without conditions, and saves as one
+        let on_enter = &template.nodes["hall"].on_enter;
+        assert_eq!(on_enter[0], Trigger { conditions: vec![], effects: vec![Effect::AdjustHP(-1)] });
+        let json = serde_json::to_string(&template).unwrap();
+        assert_eq!(&serde_json::from_str::<WorldTemplate>(&json).unwrap().nodes["hall"].on_enter, on_enter);
+
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        // Starting the run counts as the first entry into the start node
+        assert_eq!(state.log[1], "Pigeons scatter as you arrive.");
+
+        state.apply_action(GameAction::Move("hall".to_string()));
+        assert_eq!(state.log[2], "The fountain falls silent behind you.");
+        assert_eq!(state.log[3], "You move to: Hall.");
+        assert_eq!(state.log[4], "Trumpets sound!");
+        assert_eq!(state.log.len(), 5);
+        assert_eq!(state.player.hp, 99);
+        assert_eq!(
+            state.get_current_description(),
+            "You step into a vast hall for the first time. A banner hangs on the wall."
+        );
+
+        state.apply_action(GameAction::Pickup("banner".to_string(), None));
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Move("hall".to_string()));
+        assert_eq!(state.player.hp, 98);
+        assert_eq!(state.log.last(), Some(&"The guards salute the banner.".to_string()));
+        assert!(!state.log.iter().skip(5).any(|l| l == "Trumpets sound!"));
+        assert_eq!(state.log.iter().filter(|l| *l == "Pigeons scatter as you arrive.").count(), 1);
+        assert_eq!(state.get_current_description(), "The hall. A bare hook juts from the wall.");
+
+        // A teleport arrives the same way a move does, just without an edge
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_effects(&[Effect::Teleport("hall".to_string())]).unwrap();
+        let lines: Vec<String> = state.events.iter().flat_map(|e| EnglishRenderer.render(&state.world, e)).collect();
+        assert_eq!(
+            lines,
+            vec!["The fountain falls silent behind you.", "You are whisked away to hall.", "Trumpets sound!"]
+        );
+        assert_eq!(state.player.hp, 99);
+        assert_eq!(state.player.came_from.as_deref(), Some("start"));
+    }
+
+    #[test]
+    fn test_victory_and_defeat() {
+        let mut state = GameState::new("Tester".to_string());
+        state.world.victory.push(Ending {
+            conditions: vec![Condition { condition_type: ConditionType::AtNode("forest".to_string()) }],
+            message: "You reached the forest!".to_string(),
+        });
+        state.world.defeat.push(Ending {
+            conditions: vec![Condition { condition_type: ConditionType::MaxHP(0) }],
+            message: "You collapse.".to_string(),
+        });
+
+        state.apply_action(GameAction::Look);
+        assert_eq!(state.status, GameStatus::Playing);
+
+        // Defeat wins over victory on the same turn
+        let mut doomed = state.clone();
+        doomed.player.hp = 0;
+        doomed.apply_action(GameAction::Move("forest".to_string()));
+        assert_eq!(doomed.status, GameStatus::Lost);
+        assert_eq!(doomed.ending.as_deref(), Some("You collapse."));
+
+        state.apply_action(GameAction::Move("forest".to_string()));
+        assert_eq!(state.status, GameStatus::Won);
+        assert_eq!(state.log.last(), Some(&"You reached the forest!".to_string()));
+
+        // Finished runs refuse further actions
+        state.apply_action(GameAction::Move("start".to_string()));
+        assert_eq!(state.player.current_node, "forest");
+        assert_eq!(state.log.last(), Some(&"The game is over.".to_string()));
+        state.apply_action(GameAction::Look);
+        assert_eq!(state.log.last(), Some(&"Available paths: Return to the start".to_string()));
+    }
+
+    #[test]
+    fn test_undo() {
         let mut state = GameState::new("Tester".to_string());
-        
-        // Add items and combination to world
         state.world.items.insert("stick".to_string(), Item {
             id: "stick".to_string(),
             name: "Stick".to_string(),
             description: "A stick".to_string(),
             can_pickup: true,
+            on_pickup: vec![Effect::AdjustHP(-10)],
+            use_rules: vec![],
+            container: None,
+            weight: 0,
+            bulk: 1,
+            state: HashMap::new(),
+            heals: 0,
+            cures: vec![],
+            attack: 0,
+            defense: 0,
         });
-        state.world.items.insert("stone".to_string(), Item {
-            id: "stone".to_string(),
-            name: "Stone".to_string(),
-            description: "A stone".to_string(),
-            can_pickup: true,
-        });
-        state.world.items.insert("torch".to_string(), Item {
-            id: "torch".to_string(),
-            name: "Torch".to_string(),
-            description: "A torch".to_string(),
-            can_pickup: true,
+        state.world.nodes.get_mut("start").unwrap().items.add("stick", 1);
+
+        state.apply_action(state.parse_command("undo"));
+        assert_eq!(state.log.last(), Some(&"There is nothing to undo.".to_string()));
+
+        state.apply_action(GameAction::Pickup("stick".to_string(), None));
+        state.apply_action(GameAction::Look);
+        state.apply_action(GameAction::Move("forest".to_string()));
+        assert_eq!(state.history.len(), 2);
+        assert_eq!(state.history[0].nodes.keys().collect::<Vec<_>>(), ["start"]);
+        assert!(state.history[1].nodes.is_empty());
+
+        state.apply_action(state.parse_command("undo"));
+        assert_eq!(state.player.current_node, "start");
+        assert_eq!(state.turn, 1);
+        assert_eq!(state.player.visits.get("forest"), None);
+
+        state.apply_action(state.parse_command("undo 5"));
+        assert_eq!(state.turn, 0);
+        assert_eq!(state.player.hp, 100);
+        assert!(state.player.inventory.is_empty());
+        assert_eq!(state.world.nodes.get("start").unwrap().items, ["stick"].into_iter().collect());
+        assert!(state.history.is_empty());
+        assert_eq!(state.log.last(), Some(&"You rewind time by 1 turn(s).".to_string()));
+
+        state.history_limit = 3;
+        for _ in 0..5 {
+            state.apply_action(GameAction::Move("forest".to_string()));
+            state.apply_action(GameAction::Move("start".to_string()));
+        }
+        assert_eq!(state.history.len(), 3);
+        assert_eq!(state.history[0].turn, 7);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.history.len(), 3);
+        assert!(matches!(state.parse_command("undo zero"), GameAction::Invalid(_)));
+
+        let mut world = state.world.clone();
+        world.player.history_limit = Some(0);
+        let mut state = GameState::new_with_world("Tester".to_string(), world);
+        state.apply_action(GameAction::Move("forest".to_string()));
+        assert!(state.history.is_empty());
+    }
+
+    #[test]
+    fn test_game_events() {
+        let mut state = GameState::new("Tester".to_string());
+        state.world.nodes.get_mut("start").unwrap().edges.push(Edge {
+            target_id: "vault".to_string(),
+            label: "Vault".to_string(),
+            conditions: vec![
+                Condition { condition_type: ConditionType::MinHP(10) },
+                Condition { condition_type: ConditionType::HasItem("key".to_string()) },
+            ],
+            effects: vec![],
+            hidden: None,
+            hazard: None,
         });
-        state.world.combinations.push(Combination {
-            item1: "stick".to_string(),
-            item2: "stone".to_string(),
-            result: "torch".to_string(),
+        state.world.nodes.get_mut("forest").unwrap().on_enter.push(Trigger {
+            conditions: vec![],
+            effects: vec![Effect::AdjustHP(-5), Effect::GiveItem("leaf".to_string())],
         });
-        
-        // Put items in current node
-        state.world.nodes.get_mut("start").unwrap().items.push("stick".to_string());
-        state.world.nodes.get_mut("start").unwrap().items.push("stone".to_string());
-        
-        // Pickup items
-        state.apply_action(GameAction::Pickup("stick".to_string()));
-        state.apply_action(GameAction::Pickup("stone".to_string()));
-        assert!(state.player.inventory.contains(&"stick".to_string()));
-        assert!(state.player.inventory.contains(&"stone".to_string()));
-        
-        // Combine items
-        state.apply_action(GameAction::Combine("stick".to_string(), "stone".to_string()));
-        assert!(!state.player.inventory.contains(&"stick".to_string()));
-        assert!(!state.player.inventory.contains(&"stone".to_string()));
-        assert!(state.player.inventory.contains(&"torch".to_string()));
-        
-        // Use torch to unlock path (simulation of conditions)
-        let edge = Edge {
-            target_id: "cave".to_string(),
-            label: "Cave".to_string(),
-            conditions: vec![Condition {
-                condition_type: ConditionType::HasItem("torch".to_string()),
-            }],
-        };
-        assert!(state.can_traverse(&edge));
+
+        let events = state.apply_action(GameAction::Move("vault".to_string()));
+        assert_eq!(events, vec![GameEvent::MoveBlocked {
+            target_id: "vault".to_string(),
+            label: "Vault".to_string(),
+            unmet: vec![Condition { condition_type: ConditionType::HasItem("key".to_string()) }],
+        }]);
+
+        let events = state.apply_action(GameAction::Move("forest".to_string()));
+        assert_eq!(events, vec![
+            GameEvent::Moved { from: "start".to_string(), to: "forest".to_string(), label: "Go to the forest".to_string() },
+            GameEvent::HpChanged { from: 100, to: 95 },
+            GameEvent::ItemReceived("leaf".to_string()),
+        ]);
+        // Silent events do not show up in the text log
+        assert_eq!(state.log.last(), Some(&"You move to: Go to the forest.".to_string()));
+
+        let events = state.apply_action(state.parse_command("dance"));
+        assert_eq!(events, vec![GameEvent::UnknownCommand("dance".to_string())]);
+
+        struct Shouting;
+        impl EventRenderer for Shouting {
+            fn render(&self, world: &World, event: &GameEvent) -> Vec<String> {
+                EnglishRenderer.render(world, event).into_iter().map(|l| l.to_uppercase()).collect()
+            }
+        }
+        assert_eq!(Shouting.render(&state.world, &events[0]), vec!["UNKNOWN COMMAND: DANCE"]);
     }
 
     #[test]
-    fn test_yaml_deserialization() {
+    fn test_exploration_tracking() {
         let yaml = r#"
 nodes:
   start:
     id: start
-    description: "Start"
+    description: "A library"
     attributes: {}
     edges:
-      - target_id: forest
-        label: "Forest"
+      - target_id: vault
+        label: "Slip behind the bookcase"
+        conditions: []
+        hidden: {}
+      - target_id: attic
+        label: "Climb the ladder"
+        conditions: []
+        hidden: {}
+    items: [candle]
+  vault:
+    id: vault
+    description: "A vault"
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Back"
         conditions: []
+    items: [coin]
+    on_first_enter:
+      - effects:
+          - !RevealEdge [start, attic]
+  attic:
+    id: attic
+    description: "An attic"
+    attributes: {}
+    edges: []
     items: []
-  forest:
-    id: forest
-    description: "Forest"
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        let seen = |c: &str| Condition { condition_type: ConditionType::HasSeen(c.to_string()) };
+        let found = Condition {
+            condition_type: ConditionType::EdgeDiscovered("start".to_string(), "vault".to_string()),
+        };
+        assert!(state.check_condition(&seen("candle")));
+        assert!(!state.check_condition(&seen("coin")));
+
+        // Hidden edges cannot be used or seen before they are found
+        assert!(state.visible_edges().is_empty());
+        assert_eq!(state.apply_action(GameAction::Move("vault".to_string())), vec![GameEvent::NoPath("vault".to_string())]);
+
+        let events = state.apply_action(GameAction::Explore(None));
+        assert!(events.contains(&GameEvent::EdgeDiscovered {
+            node_id: "start".to_string(),
+            target_id: "vault".to_string(),
+            label: "Slip behind the bookcase".to_string(),
+            message: None,
+        }));
+        assert!(state.check_condition(&found));
+        assert_eq!(state.visible_edges().len(), 2);
+
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.player.discovered_edges.insert(("start".to_string(), "vault".to_string()));
+        state.apply_action(GameAction::Move("vault".to_string()));
+        assert!(state.check_condition(&seen("coin")));
+        state.apply_action(GameAction::Move("start".to_string()));
+        // The vault revealed the ladder on the first visit
+        assert_eq!(state.visible_edges().len(), 2);
+        state.apply_action(GameAction::Move("attic".to_string()));
+        assert_eq!(state.player.journal, vec!["start", "vault", "attic"]);
+        assert_eq!(state.player.visits.get("start"), Some(&2));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.player.discovered_edges, state.player.discovered_edges);
+        assert_eq!(restored.player.seen_items, state.player.seen_items);
+    }
+
+    #[test]
+    fn test_explore_reveals_secrets() {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        let mut world = template.to_world();
+        world.nodes.get_mut("bridge").unwrap().hidden_items.push(HiddenItem {
+            item_id: "silver_coin".to_string(),
+            feature: None,
+            conditions: vec![Condition { condition_type: ConditionType::HasItem("torch".to_string()) }],
+            message: Some("By the light of your torch you notice a silver coin wedged between the stones of the bridge.".to_string()),
+        });
+        let mut state = GameState::new_with_world("Tester".to_string(), world);
+
+        // The coin on the bridge needs a torch
+        state.apply_action(GameAction::Move("bridge".to_string()));
+        assert_eq!(state.apply_action(GameAction::Explore(None)), vec![GameEvent::NothingFound]);
+        state.player.inventory.add("torch", 1);
+        let events = state.apply_action(GameAction::Explore(None));
+        assert_eq!(events, vec![GameEvent::ItemDiscovered {
+            node_id: "bridge".to_string(),
+            item_id: "silver_coin".to_string(),
+            message: Some("By the light of your torch you notice a silver coin wedged between the stones of the bridge.".to_string()),
+        }]);
+        assert!(state.world.nodes["bridge"].items.contains("silver_coin"));
+        assert_eq!(state.apply_action(GameAction::Explore(None)), vec![GameEvent::NothingFound]);
+
+        // The note only turns up when the book itself is explored
+        state.player.discovered_edges.clear();
+        state.world.flags.insert("hut_unlocked".to_string(), "true".to_string());
+        for target in ["start", "hut_exterior", "hut_interior"] {
+            state.apply_action(GameAction::Move(target.to_string()));
+        }
+        assert_eq!(state.apply_action(GameAction::Explore(None)), vec![GameEvent::NothingFound]);
+        let events = state.apply_action(state.parse_command("explore Book"));
+        assert_eq!(state.log.last(), Some(&"Between the pages of the recipe book you find a folded note.".to_string()));
+        assert!(matches!(&events[1], GameEvent::ItemDiscovered { item_id, .. } if item_id == "recipe_note"));
+        state.apply_action(GameAction::Pickup("recipe_note".to_string(), None));
+        assert!(state.player.inventory.contains("recipe_note"));
+
+        // What was revealed stays revealed in the saved run
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+        assert!(restored.world.nodes["hut_interior"].hidden_items.is_empty());
+        assert!(restored.world.nodes["bridge"].hidden_items.is_empty());
+    }
+
+    #[test]
+    fn test_features() {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(GameAction::Move("hut_exterior".to_string()));
+
+        // Features answer to their aliases but cannot be picked up
+        let events = state.apply_action(state.parse_command("explore hut door"));
+        assert!(matches!(&events[0], GameEvent::FeatureExamined { feature_id, .. } if feature_id == "door"));
+        state.apply_action(GameAction::Pickup("door".to_string(), None));
+        assert!(state.player.inventory.is_empty());
+        let events = state.apply_action(state.parse_command("use bench"));
+        assert_eq!(events, vec![GameEvent::FeatureUsed {
+            feature_id: "bench".to_string(),
+            message: Some("You sit on the bench for a moment and listen to the trees whisper.".to_string()),
+        }]);
+
+        // The key's own rule targets the door by id, the alias finds it too
+        state.player.inventory.add("iron_key", 1);
+        state.apply_action(state.parse_command("use iron_key on hut door"));
+        assert_eq!(state.world.flags.get("hut_unlocked"), Some(&"true".to_string()));
+
+        // Rules on the feature cover items that know nothing about it
+        state.apply_action(GameAction::Move("hut_interior".to_string()));
+        state.player.inventory.add("torch", 1);
+        state.apply_action(state.parse_command("use torch on hearth"));
+        assert_eq!(state.log.last(), Some(&"You hold the torch to the old ashes, but there is nothing left to burn.".to_string()));
+        let events = state.apply_action(state.parse_command("use iron_key on fireplace"));
+        assert!(matches!(&events[0], GameEvent::NothingHappens { .. }));
+
+        // The barrier is gone once broken
+        state.world.flags.insert("barrier_broken".to_string(), "true".to_string());
+        for node_id in ["hut_exterior", "start", "bridge", "castle_gate"] {
+            state.apply_action(GameAction::Move(node_id.to_string()));
+        }
+        assert_eq!(state.player.current_node, "castle_gate");
+        assert!(state.find_feature("pedestal").is_some());
+        assert!(state.find_feature("barrier").is_none());
+    }
+
+    #[test]
+    fn test_item_stacks() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A workshop."
+    attributes: {}
+    edges: []
+    items:
+      - feather
+      - { item_id: stick, count: 3 }
+      - { item_id: gold_coin, count: 5 }
+items:
+  stick: { id: stick, name: "Stick", description: "A stick.", can_pickup: true }
+  feather: { id: feather, name: "Feather", description: "A feather.", can_pickup: true }
+  arrow: { id: arrow, name: "Arrow", description: "An arrow.", can_pickup: true }
+  gold_coin: { id: gold_coin, name: "Gold Coin", description: "A coin.", can_pickup: true }
+combinations:
+  - item1: { item_id: stick, count: 2 }
+    item2: feather
+    result: { item_id: arrow, count: 3 }
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+
+        // Counts and plural names in commands
+        assert_eq!(state.parse_command("drop 2 coins"), GameAction::Drop("gold_coin".to_string(), Some(2)));
+        assert_eq!(state.parse_command("take 2 gold coins"), GameAction::Pickup("gold_coin".to_string(), Some(2)));
+        assert_eq!(state.parse_command("use coin"), GameAction::Use("gold_coin".to_string(), None));
+        assert_eq!(state.parse_command("explore coins"), GameAction::Explore(Some("gold_coin".to_string())));
+        assert_eq!(
+            state.parse_command("put coins in sticks"),
+            GameAction::PutIn("gold_coin".to_string(), "stick".to_string())
+        );
+        state.apply_action(state.parse_command("pickup 2 gold_coins"));
+        assert_eq!(state.player.inventory.count("gold_coin"), 2);
+        assert_eq!(state.world.nodes["start"].items.count("gold_coin"), 3);
+        let events = state.apply_action(state.parse_command("drop 3 coins"));
+        assert_eq!(events, vec![GameEvent::NotEnough { item_id: "gold_coin".to_string(), available: 2 }]);
+        state.apply_action(state.parse_command("drop 1 coin"));
+        assert_eq!(state.log.last(), Some(&"You dropped: Gold Coin".to_string()));
+        state.apply_action(state.parse_command("pickup coins"));
+        assert_eq!(state.player.inventory.count("gold_coin"), 5);
+        assert!(!state.world.nodes["start"].items.contains("gold_coin"));
+
+        // Recipes consume and produce exactly their counts, not every copy
+        state.apply_action(state.parse_command("pickup sticks"));
+        state.apply_action(state.parse_command("pickup feather"));
+        state.apply_action(state.parse_command("combine stick feather"));
+        assert_eq!(state.player.inventory.count("stick"), 1);
+        assert_eq!(state.player.inventory.count("arrow"), 3);
+        assert_eq!(state.log.last(), Some(&"You combined them and created: 3x Arrow!".to_string()));
+        state.player.inventory.add("feather", 1);
+        let events = state.apply_action(state.parse_command("combine stick feather"));
+        assert_eq!(events, vec![GameEvent::MissingIngredients(vec!["stick".to_string()])]);
+
+        // Single items stay plain ids when written back
+        let yaml = WorldTemplate::from_world(&state.world).to_yaml();
+        assert!(yaml.contains("item2: feather"));
+        assert_eq!(WorldTemplate::from_yaml(&yaml).unwrap().combinations, template.combinations);
+    }
+
+    #[test]
+    fn test_carry_limits() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A smithy."
     attributes: {}
     edges:
-      - target_id: cave
-        label: "Cave"
+      - target_id: ladder
+        label: "Climb the ladder"
         conditions:
-          - !HasItem torch
+          - !CarriedWeight ["<=", 5]
+    items: [anvil, hammer, satchel, { item_id: coin, count: 10 }]
+  ladder:
+    id: ladder
+    description: "A rickety ladder."
+    attributes: {}
+    edges: []
     items: []
 items:
-  torch:
-    id: torch
-    name: "Torch"
-    description: "Torch"
+  anvil: { id: anvil, name: "Anvil", description: "Heavy.", can_pickup: true, weight: 50 }
+  hammer: { id: hammer, name: "Hammer", description: "A hammer.", can_pickup: true, weight: 4 }
+  coin: { id: coin, name: "Coin", description: "A coin.", can_pickup: true, bulk: 0 }
+  satchel:
+    id: satchel
+    name: "Satchel"
+    description: "A small bag."
     can_pickup: true
-combinations: []
+    weight: 1
+    container:
+      contents: [{ item_id: ingot, count: 2 }]
+  ingot: { id: ingot, name: "Ingot", description: "An iron ingot.", can_pickup: true, weight: 3 }
+player:
+  max_carry_weight: 12
+  max_slots: 2
 "#;
         let template = WorldTemplate::from_yaml(yaml).unwrap();
-        let forest = template.nodes.get("forest").unwrap();
-        assert_eq!(forest.edges[0].conditions.len(), 1);
-        match &forest.edges[0].conditions[0].condition_type {
-            ConditionType::HasItem(item) => assert_eq!(item, "torch"),
-            _ => panic!("Expected HasItem condition"),
-        }
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        assert_eq!(state.player.max_carry_weight, Some(12));
+
+        let events = state.apply_action(GameAction::Pickup("anvil".to_string(), None));
+        assert_eq!(events, vec![GameEvent::TooHeavy { item_id: "anvil".to_string(), carried: 0, max: 12 }]);
+        assert_eq!(state.log.last(), Some(&"The Anvil is too heavy to carry as well. You are carrying 0 of at most 12.".to_string()));
+
+        // A bag weighs as much as it holds; coins take no slot
+        state.apply_action(GameAction::Pickup("satchel".to_string(), None));
+        assert_eq!(state.carried_weight(), 7);
+        state.apply_action(GameAction::Pickup("coin".to_string(), None));
+        state.apply_action(GameAction::Pickup("hammer".to_string(), None));
+        assert_eq!(state.used_slots(), 2);
+        assert_eq!(state.carried_weight(), 11);
+        assert!(!state.can_traverse(&state.world.nodes["start"].edges[0]));
+
+        state.player.max_carry_weight = None;
+        state.world.nodes.get_mut("start").unwrap().items.add("anvil", 1);
+        let events = state.apply_action(GameAction::Pickup("anvil".to_string(), None));
+        assert_eq!(events, vec![GameEvent::InventoryFull { item_id: "anvil".to_string(), max_slots: 2 }]);
+
+        // Lightening the load opens the way
+        state.apply_action(GameAction::Drop("hammer".to_string(), None));
+        state.apply_action(GameAction::Drop("satchel".to_string(), None));
+        assert_eq!(state.carried_weight(), 0);
+        state.apply_action(GameAction::Move("ladder".to_string()));
+        assert_eq!(state.player.current_node, "ladder");
+
+        // Absurd weights saturate instead of wrapping around to a light load
+        state.world.items.get_mut("anvil").unwrap().weight = u32::MAX;
+        state.world.nodes.get_mut("ladder").unwrap().items.add("anvil", 2);
+        state.player.max_carry_weight = Some(12);
+        assert_eq!(state.stack_weight(&ItemStack::new("anvil", 2)), u32::MAX);
+        let events = state.apply_action(GameAction::Pickup("anvil".to_string(), None));
+        assert_eq!(events, vec![GameEvent::TooHeavy { item_id: "anvil".to_string(), carried: 0, max: 12 }]);
     }
 
     #[test]
-    fn test_whispering_woods_walkthrough() {
-        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
-        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
-        let mut state = GameState::new_with_world("Hero".to_string(), template.to_world());
+    fn test_multi_ingredient_recipes() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A forge."
+    attributes: {}
+    edges: []
+    items: [anvil, { item_id: ore, count: 2 }, coal]
+items:
+  anvil: { id: anvil, name: "Anvil", description: "Heavy.", can_pickup: false }
+  hammer: { id: hammer, name: "Hammer", description: "A hammer.", can_pickup: true }
+  ore: { id: ore, name: "Iron Ore", description: "Rough ore.", can_pickup: true }
+  coal: { id: coal, name: "Coal", description: "Black coal.", can_pickup: true }
+  blade: { id: blade, name: "Blade", description: "A fresh blade.", can_pickup: true }
+  slag: { id: slag, name: "Slag", description: "Leftovers.", can_pickup: true }
+combinations:
+  - ingredients: [{ item_id: ore, count: 2 }, coal]
+    tools: [hammer, anvil]
+    results: [blade, slag]
+    conditions:
+      - !AttributeCompare [smithing, ">=", 1]
+    message: "You hammer out a blade."
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        assert!(!template.validate().iter().any(|d| d.is_error()));
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(state.parse_command("pickup ore"));
+        state.apply_action(state.parse_command("pickup coal"));
 
-        // 1. Start at the Forest Crossroads. Pick up the flint.
-        assert_eq!(state.player.current_node, "start");
-        state.apply_action(GameAction::Pickup("flint".to_string()));
-        assert!(state.player.inventory.contains(&"flint".to_string()));
+        // Tools may be named or left out, but must be at hand
+        let events = state.apply_action(state.parse_command("combine ore and coal"));
+        assert_eq!(events, vec![GameEvent::MissingIngredients(vec!["hammer".to_string()])]);
+        state.world.nodes.get_mut("start").unwrap().items.add("hammer", 1);
+        let events = state.apply_action(state.parse_command("combine ore coal hammer"));
+        assert_eq!(events, vec![GameEvent::CombineBlocked(vec!["ore".to_string(), "coal".to_string(), "hammer".to_string()])]);
 
-        // 2. Go to the Stone Bridge and pick up the dry_wood.
-        state.apply_action(GameAction::Move("bridge".to_string()));
-        assert_eq!(state.player.current_node, "bridge");
-        state.apply_action(GameAction::Pickup("dry_wood".to_string()));
-        assert!(state.player.inventory.contains(&"dry_wood".to_string()));
+        state.player.attributes.insert("smithing".to_string(), "2".to_string());
+        // The results have to fit in place of the ingredients
+        state.player.max_slots = Some(1);
+        let events = state.apply_action(state.parse_command("combine ore coal"));
+        assert_eq!(events, vec![GameEvent::InventoryFull { item_id: "slag".to_string(), max_slots: 1 }]);
+        assert_eq!(state.player.inventory.count("ore"), 2);
+        assert!(!state.player.inventory.contains("blade"));
+        state.player.max_slots = Some(2);
+        let events = state.apply_action(state.parse_command("combine ore coal"));
+        assert!(matches!(&events[0], GameEvent::Combined { combination: 0, results, .. } if results.len() == 2));
+        assert_eq!(state.log.last(), Some(&"You hammer out a blade.".to_string()));
+        assert!(state.world.nodes["start"].items.contains("hammer"));
+        assert!(state.player.inventory.contains("slag"));
+        assert!(!state.player.inventory.contains("ore"));
 
-        // 3. Combine flint and dry_wood to create a torch.
-        state.apply_action(GameAction::Combine("flint".to_string(), "dry_wood".to_string()));
-        assert!(state.player.inventory.contains(&"torch".to_string()));
-        assert!(!state.player.inventory.contains(&"flint".to_string()));
-        assert!(!state.player.inventory.contains(&"dry_wood".to_string()));
+        let events = state.apply_action(state.parse_command("combine blade slag"));
+        assert_eq!(events, vec![GameEvent::CannotCombine(vec!["blade".to_string(), "slag".to_string()])]);
+        let yaml = WorldTemplate::from_world(&state.world).to_yaml();
+        assert!(yaml.contains("tools:") && !yaml.contains("item1"));
+    }
 
-        // 4. Go to the Forgotten Path, pick up wild_herbs, and use the torch to enter the Echoing Cave.
-        state.apply_action(GameAction::Move("start".to_string()));
-        state.apply_action(GameAction::Move("forgotten_path".to_string()));
-        assert_eq!(state.player.current_node, "forgotten_path");
-        state.apply_action(GameAction::Pickup("wild_herbs".to_string()));
-        assert!(state.player.inventory.contains(&"wild_herbs".to_string()));
+    #[test]
+    fn test_item_state() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A dark stair."
+    attributes: {}
+    edges:
+      - target_id: cellar
+        label: "Go down"
+        conditions:
+          - !ItemState [lamp, lit, "true"]
+    items: [lamp]
+  cellar:
+    id: cellar
+    description: "A cellar."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  lamp:
+    id: lamp
+    name: "Oil Lamp"
+    description: "A brass lamp."
+    can_pickup: true
+    state: { lit: "false", oil: "2" }
+    use_rules:
+      - conditions:
+          - !ItemState [lamp, lit, "false"]
+        effects:
+          - !SetItemState [lamp, lit, "true"]
+        message: "You light the lamp."
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(GameAction::Look);
+        assert!(state.log.contains(&"Items here: Oil Lamp (oil 2)".to_string()));
+        state.apply_action(GameAction::Pickup("lamp".to_string(), None));
+        assert!(!state.can_traverse(&state.world.nodes["start"].edges[0]));
 
-        state.apply_action(GameAction::Move("cave".to_string()));
-        assert_eq!(state.player.current_node, "cave");
+        let events = state.apply_action(state.parse_command("use lamp"));
+        assert!(events.contains(&GameEvent::ItemStateChanged {
+            item_id: "lamp".to_string(),
+            key: "lit".to_string(),
+            value: "true".to_string(),
+        }));
+        state.apply_action(GameAction::Inventory);
+        assert_eq!(state.log.last(), Some(&"You are carrying: Oil Lamp (lit, oil 2)".to_string()));
+        assert!(state.can_traverse(&state.world.nodes["start"].edges[0]));
 
-        // 5. Find the iron_key inside the cave.
-        state.apply_action(GameAction::Pickup("iron_key".to_string()));
-        assert!(state.player.inventory.contains(&"iron_key".to_string()));
+        assert_eq!(state.world.items["lamp"].state["lit"], "false");
 
-        // 6. Go to the Hut Exterior and use the iron_key to enter the Hut Interior.
-        state.apply_action(GameAction::Move("forgotten_path".to_string()));
-        state.apply_action(GameAction::Move("start".to_string()));
-        state.apply_action(GameAction::Move("hut_exterior".to_string()));
-        assert_eq!(state.player.current_node, "hut_exterior");
-        
-        state.apply_action(GameAction::Move("hut_interior".to_string()));
-        assert_eq!(state.player.current_node, "hut_interior");
+        // The state lives on the carried lamp, so it is saved and undone with it
+        state.apply_action(GameAction::Undo(1));
+        assert!(state.player.inventory.get(0).unwrap().state.is_empty());
+        let events = state.apply_action(GameAction::Move("cellar".to_string()));
+        assert!(matches!(events[0], GameEvent::MoveBlocked { .. }));
 
-        // 7. Pick up the cauldron and combine it with wild_herbs to brew the purifying_potion.
-        state.apply_action(GameAction::Pickup("cauldron".to_string()));
-        assert!(state.player.inventory.contains(&"cauldron".to_string()));
-        
-        state.apply_action(GameAction::Combine("wild_herbs".to_string(), "cauldron".to_string()));
-        assert!(state.player.inventory.contains(&"purifying_potion".to_string()));
+        // Two lamps, one lit: the stack splits, and lighting the other joins it again
+        state.world.nodes.get_mut("start").unwrap().items.add("lamp", 1);
+        state.apply_action(GameAction::Pickup("lamp".to_string(), None));
+        state.apply_action(state.parse_command("use lamp"));
+        state.apply_action(GameAction::Inventory);
+        assert_eq!(state.log.last(), Some(&"You are carrying: Oil Lamp (oil 2), Oil Lamp (lit, oil 2)".to_string()));
+        state.apply_action(state.parse_command("drop 1 lamp"));
+        assert!(state.world.nodes["start"].items.get(0).unwrap().state.is_empty());
+        assert_eq!(state.state_of(state.player.inventory.get(0).unwrap(), "lit"), Some(&"true".to_string()));
+        assert!(state.can_traverse(&state.world.nodes["start"].edges[0]));
+        // The unlit lamp on the floor is the one that gets lit
+        state.apply_action(state.parse_command("use lamp"));
+        assert_eq!(state.world.nodes["start"].items.get(0).unwrap().state["lit"], "true");
+        state.apply_action(GameAction::Pickup("lamp".to_string(), None));
+        assert_eq!(state.player.inventory.len(), 1);
+        assert_eq!(state.player.inventory.count("lamp"), 2);
+        let yaml = WorldTemplate::from_world(&state.world).to_yaml();
+        assert!(yaml.contains("state:"));
+    }
 
-        // 8. Travel through the Stone Bridge to the Castle Gate.
-        state.apply_action(GameAction::Move("hut_exterior".to_string()));
+    #[test]
+    fn test_hp_hazards_and_death() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A camp."
+    attributes: {}
+    checkpoint: true
+    edges:
+      - target_id: swamp
+        label: "Wade into the swamp"
+        conditions: []
+        hazard: { damage: 4, message: "Leeches!" }
+    items: [salve]
+  swamp:
+    id: swamp
+    description: "A stinking swamp."
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Back to camp"
+        conditions: []
+    items: []
+    on_enter:
+      - effects:
+          - !AddStatus { id: poison, hp_per_turn: -2, turns: 2 }
+items:
+  salve: { id: salve, name: "Salve", description: "Soothing.", can_pickup: true, heals: 5, cures: [poison] }
+player:
+  max_hp: 10
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        let events = state.apply_action(GameAction::Use("salve".to_string(), None));
+        assert_eq!(events, vec![GameEvent::NotCarrying("salve".to_string())]);
+        state.apply_action(GameAction::Pickup("salve".to_string(), None));
+        let events = state.apply_action(GameAction::Use("salve".to_string(), None));
+        assert_eq!(events, vec![GameEvent::NoNeedToConsume("salve".to_string())]);
+
+        // Hazard on the way in, then the poison bites at the end of the turn
+        state.apply_action(GameAction::Move("swamp".to_string()));
+        assert_eq!(state.log[state.log.len() - 2], "Leeches! You lose 4 HP.");
+        assert_eq!(state.log.last(), Some(&"The poison costs you 2 HP.".to_string()));
+        assert_eq!(state.player.hp, 4);
+
+        state.apply_action(state.parse_command("use salve"));
+        assert_eq!(state.player.hp, 9);
+        assert!(state.player.statuses.is_empty());
+        assert!(!state.player.inventory.contains("salve"));
+
+        // The camp stays the place to respawn after returning to it
+        state.player.hp = 4;
         state.apply_action(GameAction::Move("start".to_string()));
-        state.apply_action(GameAction::Move("bridge".to_string()));
-        state.apply_action(GameAction::Move("castle_gate".to_string()));
-        assert_eq!(state.player.current_node, "castle_gate");
+        assert_eq!(state.player.checkpoint.as_deref(), Some("start"));
+        let events = state.apply_action(GameAction::Move("swamp".to_string()));
+        assert_eq!(events.last(), Some(&GameEvent::Respawned("start".to_string())));
+        assert_eq!(state.player.current_node, "start");
+        assert_eq!(state.player.hp, 10);
+        assert!(state.player.statuses.is_empty());
 
-        // 9. Use the purifying_potion to break the seal and enter the Castle Keep.
-        state.apply_action(GameAction::Move("castle_keep".to_string()));
-        assert_eq!(state.player.current_node, "castle_keep");
+        // Starting in the camp makes it the checkpoint before it is ever left
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        assert_eq!(state.player.checkpoint.as_deref(), Some("start"));
+        state.player.hp = 3;
+        let events = state.apply_action(GameAction::Move("swamp".to_string()));
+        assert_eq!(events.last(), Some(&GameEvent::Respawned("start".to_string())));
+        assert_eq!(state.status, GameStatus::Playing);
 
-        // 10. Retrieve the artifact and lift the curse.
-        state.apply_action(GameAction::Pickup("artifact".to_string()));
-        assert!(state.player.inventory.contains(&"artifact".to_string()));
+        // Dying without a checkpoint ends the run
+        let mut world = template.to_world();
+        world.nodes.get_mut("start").unwrap().checkpoint = false;
+        let mut state = GameState::new_with_world("Tester".to_string(), world.clone());
+        state.player.hp = 3;
+        state.apply_action(GameAction::Move("swamp".to_string()));
+        assert_eq!(state.status, GameStatus::Lost);
+        assert_eq!(state.ending.as_deref(), Some("You have died."));
+
+        // Huge numbers neither wrap around into healing nor overflow the HP bar
+        let mut state = GameState::new_with_world("Tester".to_string(), world);
+        let edge = &mut state.world.nodes.get_mut("start").unwrap().edges[0];
+        edge.hazard.as_mut().unwrap().damage = u32::MAX;
+        let events = state.apply_action(GameAction::Move("swamp".to_string()));
+        assert!(events.contains(&GameEvent::HazardHit { damage: 10, message: Some("Leeches!".to_string()) }));
+        assert_eq!(state.status, GameStatus::Lost);
+        state.player.hp = u32::MAX;
+        state.player.max_hp = u32::MAX;
+        assert_eq!(state.player.hp_percent(), 100);
+    }
+
+    #[test]
+    fn test_containers() {
+        let mut state = GameState::new("Tester".to_string());
+        let container = |contents: &[&str], locked: bool| Container {
+            contents: contents.iter().map(|id| id.to_string()).collect(),
+            open: false,
+            locked,
+            unlock_conditions: vec![Condition { condition_type: ConditionType::HasItem("chest_key".to_string()) }],
+        };
+        for (id, container) in [
+            ("chest", Some(container(&["bag"], true))),
+            ("bag", Some(container(&["coin"], false))),
+            ("coin", None),
+            ("chest_key", None),
+        ] {
+            state.world.items.insert(id.to_string(), Item {
+                id: id.to_string(),
+                name: id.to_string(),
+                description: String::new(),
+                can_pickup: id != "chest",
+                on_pickup: vec![],
+                use_rules: vec![],
+                container,
+                weight: 0,
+                bulk: 1,
+                state: HashMap::new(),
+                heals: 0,
+                cures: vec![],
+                attack: 0,
+                defense: 0,
+            });
+        }
+        state.world.nodes.get_mut("start").unwrap().items.add("chest", 1);
+
+        // Locked until the key is carried, then the bag inside can be opened too
+        assert_eq!(state.apply_action(state.parse_command("open chest")), vec![GameEvent::ContainerLocked("chest".to_string())]);
+        assert_eq!(state.apply_action(state.parse_command("take bag from chest")), vec![GameEvent::ContainerIsClosed("chest".to_string())]);
+        state.player.inventory.add("chest_key", 1);
+        let events = state.apply_action(state.parse_command("open chest"));
+        assert_eq!(events[1], GameEvent::ContainerOpened { container_id: "chest".to_string(), contents: ["bag"].into_iter().collect() });
+        state.apply_action(state.parse_command("open bag"));
+        assert!(state.reachable_items().contains(&"coin".to_string()));
+        assert!(state.player.seen_items.contains("coin"));
+
+        // Taking the bag carries its contents along
+        state.apply_action(state.parse_command("take bag from chest"));
+        assert_eq!(state.player.inventory.ids().collect::<Vec<_>>(), ["chest_key", "bag"]);
+        state.apply_action(state.parse_command("put chest_key into bag"));
+        let bag = state.player.inventory.get(0).unwrap();
+        assert_eq!(state.container_of(bag).unwrap().contents, ["coin", "chest_key"].into_iter().collect());
+        assert_eq!(state.world.items["bag"].container.as_ref().unwrap().contents, ["coin"].into_iter().collect());
+        let events = state.apply_action(state.parse_command("put bag in bag"));
+        assert!(matches!(&events[0], GameEvent::CannotPutInside { .. }));
+        state.apply_action(state.parse_command("close bag"));
+        assert_eq!(state.apply_action(state.parse_command("take coin from bag")), vec![GameEvent::ContainerIsClosed("bag".to_string())]);
+
+        // Every bag has contents of its own; opening one of a stack splits it off
+        state.world.nodes.get_mut("start").unwrap().items.add("bag", 2);
+        state.apply_action(state.parse_command("drop bag"));
+        state.apply_action(state.parse_command("open bag"));
+        let node_bags = |state: &GameState| -> Vec<(u32, bool)> {
+            let items = &state.world.nodes["start"].items;
+            items.iter().filter(|s| s.item_id == "bag").map(|s| (s.count, state.container_of(s).unwrap().open)).collect()
+        };
+        assert_eq!(node_bags(&state), vec![(1, false), (1, true), (1, false)]);
+        state.apply_action(state.parse_command("take coin from bag"));
+        assert_eq!(state.player.inventory.count("coin"), 1);
+        let dropped = state.world.nodes["start"].items.iter().last().unwrap();
+        assert_eq!(state.container_of(dropped).unwrap().contents, ["coin", "chest_key"].into_iter().collect());
+
+        // Contents and open state round-trip through the YAML format
+        let template = WorldTemplate::from_yaml(&WorldTemplate::from_world(&state.world).to_yaml()).unwrap();
+        assert_eq!(template.items["chest"].container, state.world.items["chest"].container);
+        assert_eq!(template.nodes["start"].items, state.world.nodes["start"].items);
+
+        // A definition that holds itself, which validate reports, is looked into only once
+        let bag = state.world.items.get_mut("bag").unwrap();
+        bag.weight = 1;
+        bag.container.as_mut().unwrap().open = true;
+        bag.container.as_mut().unwrap().contents.add("bag", 1);
+        state.world.nodes.get_mut("start").unwrap().items = ["bag"].into_iter().collect();
+        assert_eq!(state.reachable_items(), ["coin", "bag"]);
+        assert_eq!(state.stack_weight(&ItemStack::new("bag", 1)), 2);
+    }
+
+    #[test]
+    fn test_key_in_a_chest() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A cave with a chest wedged into a crevice."
+    attributes: {}
+    edges:
+      - target_id: door
+        label: "Door"
+        conditions:
+          - !HasItem iron_key
+    items: [old_chest]
+  door:
+    id: door
+    description: "An open door."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  old_chest:
+    id: old_chest
+    name: "Old Wooden Chest"
+    description: "A small chest with rusted hinges."
+    can_pickup: false
+    container:
+      contents: [iron_key]
+  iron_key: { id: iron_key, name: "Old Iron Key", description: "A heavy key.", can_pickup: true }
+victory:
+  - conditions:
+      - !AtNode door
+    message: "You are through."
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        assert!(template.validate().is_empty());
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.apply_action(state.parse_command("pickup old_chest"));
+        assert!(state.player.inventory.is_empty());
+
+        // The solver finds the key inside the chest
+        let solution = solve(&template, &Goal::Victory, &AnalysisOptions::default()).unwrap();
+        assert_eq!(solution.commands, ["open old_chest", "take iron_key from old_chest", "go door"]);
+        for command in &solution.commands {
+            state.apply_action(state.parse_command(command));
+        }
+        assert_eq!(state.status, GameStatus::Won);
     }
 }
@@ -0,0 +1,354 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::{Edge, GameState, World};
+
+const BOX_HEIGHT: usize = 3;
+const GAP_X: usize = 5;
+const GAP_Y: usize = 1;
+
+/// Fixed `position`s are clamped to this distance from the origin, so that placing
+/// nodes next to them cannot overflow.
+pub(crate) const MAX_POSITION: i32 = 10_000;
+
+type Offset = (i32, i32);
+
+/// Compass words recognised in edge labels, with their grid offset (y grows south).
+const COMPASS: [(&str, Offset); 8] = [
+    ("north", (0, -1)),
+    ("south", (0, 1)),
+    ("east", (1, 0)),
+    ("west", (-1, 0)),
+    ("northeast", (1, -1)),
+    ("northwest", (-1, -1)),
+    ("southeast", (1, 1)),
+    ("southwest", (-1, 1)),
+];
+
+/// Where to put a neighbour when no edge between the two nodes has a hint.
+const FALLBACK: [Offset; 8] = [(1, 0), (0, 1), (-1, 0), (0, -1), (1, 1), (-1, 1), (1, -1), (-1, -1)];
+
+fn compass_hint(label: &str) -> Option<Offset> {
+    label
+        .split(|c: char| !c.is_alphabetic())
+        .find_map(|word| COMPASS.iter().find(|(name, _)| word.eq_ignore_ascii_case(name)).map(|(_, offset)| *offset))
+}
+
+fn free_cell(occupied: &HashSet<(i32, i32)>, from: (i32, i32), hint: Option<Offset>) -> (i32, i32) {
+    let offsets: Vec<Offset> = match hint {
+        Some(offset) => vec![offset],
+        None => FALLBACK.to_vec(),
+    };
+    (1..)
+        .flat_map(|distance| offsets.iter().map(move |(dx, dy)| (from.0 + dx * distance, from.1 + dy * distance)))
+        .find(|cell| !occupied.contains(cell))
+        .expect("the grid is unbounded")
+}
+
+/// Assigns every node a grid cell. Nodes with a fixed `position` keep it; the rest
+/// are placed breadth-first from the start, next to a neighbour in the direction
+/// named by the edge label (or opposite to the label of the edge coming back).
+/// Nodes not connected to the start begin new rows below the map.
+fn layout(world: &World) -> HashMap<String, (i32, i32)> {
+    let mut ids: Vec<&String> = world.nodes.keys().collect();
+    ids.sort();
+
+    let mut neighbours: HashMap<&str, Vec<(&str, Option<Offset>)>> = HashMap::new();
+    for id in &ids {
+        for edge in &world.nodes[*id].edges {
+            if edge.target_id == **id || !world.nodes.contains_key(&edge.target_id) {
+                continue;
+            }
+            let hint = compass_hint(&edge.label);
+            neighbours.entry(id.as_str()).or_default().push((&edge.target_id, hint));
+            neighbours.entry(edge.target_id.as_str()).or_default().push((id.as_str(), hint.map(|(dx, dy)| (-dx, -dy))));
+        }
+    }
+
+    let mut positions: HashMap<String, (i32, i32)> = HashMap::new();
+    let mut occupied: HashSet<(i32, i32)> = HashSet::new();
+    for id in &ids {
+        if let Some(position) = world.nodes[*id].position.map(|(x, y)| {
+            (x.clamp(-MAX_POSITION, MAX_POSITION), y.clamp(-MAX_POSITION, MAX_POSITION))
+        }) && occupied.insert(position)
+        {
+            positions.insert(id.to_string(), position);
+        }
+    }
+
+    let mut roots: Vec<&str> = ids.iter().map(|id| id.as_str()).collect();
+    if let Some(index) = roots.iter().position(|id| *id == "start") {
+        roots.remove(index);
+        roots.insert(0, "start");
+    }
+
+    let mut expanded: HashSet<&str> = HashSet::new();
+    for root in roots {
+        if expanded.contains(root) {
+            continue;
+        }
+        if !positions.contains_key(root) {
+            let cell = match (occupied.iter().map(|c| c.0).min(), occupied.iter().map(|c| c.1).max()) {
+                (Some(min_x), Some(max_y)) => (min_x, max_y + 2),
+                _ => (0, 0),
+            };
+            occupied.insert(cell);
+            positions.insert(root.to_string(), cell);
+        }
+
+        let mut queue = VecDeque::from([root]);
+        while let Some(id) = queue.pop_front() {
+            if !expanded.insert(id) {
+                continue;
+            }
+            let from = positions[id];
+            let links = neighbours.get(id).map(Vec::as_slice).unwrap_or_default();
+            for (target, _) in links {
+                if !positions.contains_key(*target) {
+                    let hint = links.iter().filter(|(other, _)| other == target).find_map(|(_, hint)| *hint);
+                    let cell = free_cell(&occupied, from, hint);
+                    occupied.insert(cell);
+                    positions.insert(target.to_string(), cell);
+                }
+                queue.push_back(target);
+            }
+        }
+    }
+    positions
+}
+
+/// Border piece where a path leaves a box on the given side.
+fn junction(border: char) -> char {
+    match border {
+        '─' => '┬',
+        '═' => '╤',
+        '│' => '├',
+        '║' => '╟',
+        other => other,
+    }
+}
+
+fn junction_opposite(border: char) -> char {
+    match border {
+        '─' => '┴',
+        '═' => '╧',
+        '│' => '┤',
+        '║' => '╢',
+        other => other,
+    }
+}
+
+/// Numbers the distinct values of one axis from 0, closing every gap of more
+/// than one empty cell down to a single one, so that far-apart positions do
+/// not make the canvas huge and only neighbours end up next to each other.
+fn compact(values: impl Iterator<Item = i32>) -> HashMap<i32, usize> {
+    let values: BTreeSet<i32> = values.collect();
+    let mut cells = HashMap::new();
+    let mut next = 0;
+    let mut previous: Option<i32> = None;
+    for value in values {
+        if previous.is_some_and(|p| value.abs_diff(p) > 1) {
+            next += 1;
+        }
+        cells.insert(value, next);
+        next += 1;
+        previous = Some(value);
+    }
+    cells
+}
+
+/// Draws the shown nodes as boxes on their layout cells. Neighbouring cells are
+/// joined by lines, other connections are listed below the map. A path is drawn
+/// as locked when `locked` holds for either of its directions; edges failing
+/// `known` are left out.
+fn render(
+    world: &World,
+    current: Option<&str>,
+    shown: &dyn Fn(&str) -> bool,
+    known: &dyn Fn(&str, &Edge) -> bool,
+    locked: &dyn Fn(&Edge) -> bool,
+) -> String {
+    let positions = layout(world);
+    let mut ids: Vec<&String> = world.nodes.keys().filter(|id| shown(id)).collect();
+    ids.sort();
+    if ids.is_empty() {
+        return "There is nothing to map yet.".to_string();
+    }
+
+    let box_width = world.nodes.keys().map(|id| id.chars().count()).max().unwrap_or(0) + 4;
+    let columns = compact(ids.iter().map(|id| positions[*id].0));
+    let rows = compact(ids.iter().map(|id| positions[*id].1));
+    let cell = |id: &str| (columns[&positions[id].0], rows[&positions[id].1]);
+    let width = (columns.values().max().unwrap_or(&0) + 1) * (box_width + GAP_X) - GAP_X;
+    let height = (rows.values().max().unwrap_or(&0) + 1) * (BOX_HEIGHT + GAP_Y) - GAP_Y;
+    let origin = |id: &str| {
+        let (x, y) = cell(id);
+        (x * (box_width + GAP_X), y * (BOX_HEIGHT + GAP_Y))
+    };
+
+    let mut canvas = vec![vec![' '; width]; height];
+    for id in &ids {
+        let (col, row) = origin(id);
+        let [top_left, horizontal, top_right, vertical, bottom_left, bottom_right] = if current == Some(id.as_str()) {
+            ['╔', '═', '╗', '║', '╚', '╝']
+        } else {
+            ['┌', '─', '┐', '│', '└', '┘']
+        };
+        for i in 1..box_width - 1 {
+            canvas[row][col + i] = horizontal;
+            canvas[row + 2][col + i] = horizontal;
+        }
+        canvas[row][col] = top_left;
+        canvas[row][col + box_width - 1] = top_right;
+        canvas[row + 1][col] = vertical;
+        canvas[row + 1][col + box_width - 1] = vertical;
+        canvas[row + 2][col] = bottom_left;
+        canvas[row + 2][col + box_width - 1] = bottom_right;
+        for (i, ch) in id.chars().enumerate() {
+            canvas[row + 1][col + 2 + i] = ch;
+        }
+    }
+
+    let mut paths: BTreeMap<(&str, &str), bool> = BTreeMap::new();
+    for id in &ids {
+        for edge in &world.nodes[*id].edges {
+            if edge.target_id == **id
+                || !shown(&edge.target_id)
+                || !known(id, edge)
+                || !world.nodes.contains_key(&edge.target_id)
+            {
+                continue;
+            }
+            let key = if id.as_str() < edge.target_id.as_str() {
+                (id.as_str(), edge.target_id.as_str())
+            } else {
+                (edge.target_id.as_str(), id.as_str())
+            };
+            *paths.entry(key).or_insert(false) |= locked(edge);
+        }
+    }
+
+    let mut others = Vec::new();
+    for ((a, b), is_locked) in paths {
+        let (pa, pb) = (cell(a), cell(b));
+        if pa.1 == pb.1 && pa.0.abs_diff(pb.0) == 1 {
+            let (col, row) = origin(if pa.0 < pb.0 { a } else { b });
+            let right = col + box_width - 1;
+            canvas[row + 1][right] = junction(canvas[row + 1][right]);
+            canvas[row + 1][right + GAP_X + 1] = junction_opposite(canvas[row + 1][right + GAP_X + 1]);
+            for i in 1..=GAP_X {
+                canvas[row + 1][right + i] = if is_locked { '╌' } else { '─' };
+            }
+        } else if pa.0 == pb.0 && pa.1.abs_diff(pb.1) == 1 {
+            let (col, row) = origin(if pa.1 < pb.1 { a } else { b });
+            let center = col + box_width / 2;
+            canvas[row + 2][center] = junction(canvas[row + 2][center]);
+            canvas[row + BOX_HEIGHT + GAP_Y][center] = junction_opposite(canvas[row + BOX_HEIGHT + GAP_Y][center]);
+            for i in 0..GAP_Y {
+                canvas[row + BOX_HEIGHT + i][center] = if is_locked { '┆' } else { '│' };
+            }
+        } else {
+            others.push(format!("  {} {} {}", a, if is_locked { "╌╌" } else { "──" }, b));
+        }
+    }
+
+    let mut lines: Vec<String> = canvas.into_iter().map(|row| row.into_iter().collect::<String>().trim_end().to_string()).collect();
+    if !others.is_empty() {
+        lines.push("Other paths:".to_string());
+        lines.extend(others);
+    }
+    let mut legend = Vec::new();
+    if current.is_some() {
+        legend.push("╔═╗ you are here");
+    }
+    legend.push("╌ locked path");
+    lines.push(format!("Legend: {}", legend.join(", ")));
+    lines.join("\n")
+}
+
+impl World {
+    /// The whole world as a box diagram, hidden edges included. Paths with
+    /// conditions are drawn as locked.
+    pub fn get_ascii_map(&self) -> String {
+        render(self, None, &|_| true, &|_, _| true, &|edge| !edge.conditions.is_empty())
+    }
+}
+
+impl GameState {
+    /// The world as the player sees it: their node is highlighted and paths they
+    /// cannot take right now are drawn as locked, and undiscovered hidden edges
+    /// are left out. With `fog_of_war` only visited nodes are shown.
+    pub fn get_ascii_map(&self, fog_of_war: bool) -> String {
+        let visits = &self.player.visits;
+        let current = self.player.current_node.as_str();
+        let shown = |id: &str| !fog_of_war || id == current || visits.get(id).is_some_and(|count| *count > 0);
+        let known = |node_id: &str, edge: &Edge| self.is_edge_known(node_id, edge);
+        render(&self.world, Some(current), &shown, &known, &|edge| !self.can_traverse(edge))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameAction, WorldTemplate};
+
+    fn whispering_woods() -> GameState {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        GameState::new_with_world("Tester".to_string(), template.to_world())
+    }
+
+    #[test]
+    fn test_layout_follows_compass_hints() {
+        let mut state = whispering_woods();
+        let positions = layout(&state.world);
+        assert_eq!(positions["start"], (0, 0));
+        assert_eq!(positions["bridge"], (0, -1));
+        assert_eq!(positions["castle_gate"], (0, -2));
+        assert_eq!(positions["forgotten_path"], (-1, 0));
+        assert_eq!(positions["hut_exterior"], (1, 0));
+
+        // A fixed position wins over hints
+        state.world.nodes.get_mut("bridge").unwrap().position = Some((5, 5));
+        assert_eq!(layout(&state.world)["bridge"], (5, 5));
+    }
+
+    #[test]
+    fn test_far_apart_positions_stay_small() {
+        let mut state = whispering_woods();
+        state.world.nodes.get_mut("start").unwrap().position = Some((i32::MIN, 0));
+        state.world.nodes.get_mut("bridge").unwrap().position = Some((i32::MAX, i32::MAX));
+        let positions = layout(&state.world);
+        assert_eq!(positions["start"], (-MAX_POSITION, 0));
+        assert_eq!(positions["bridge"], (MAX_POSITION, MAX_POSITION));
+
+        let map = state.world.get_ascii_map();
+        assert!(map.lines().count() < 50, "{}", map);
+        assert!(map.lines().all(|line| line.chars().count() < 200), "{}", map);
+    }
+
+    #[test]
+    fn test_map_marks_player_locks_and_fog() {
+        let mut state = whispering_woods();
+        let full = state.world.get_ascii_map();
+        for id in state.world.nodes.keys() {
+            assert!(full.contains(id.as_str()), "{} missing from\n{}", id, full);
+        }
+        assert!(!full.contains('╔'));
+        // hut_exterior -> hut_interior needs the door unlocked
+        assert!(full.contains('╌'));
+
+        let fogged = state.get_ascii_map(true);
+        assert!(fogged.contains("║ start"));
+        assert!(!fogged.contains("bridge"));
+
+        state.apply_action(GameAction::Move("bridge".to_string()));
+        let events = state.apply_action(state.parse_command("map"));
+        let Some(crate::GameEvent::MapShown(map)) = events.first() else {
+            panic!("expected a map, got {:?}", events);
+        };
+        assert!(map.contains("║ bridge"));
+        assert!(map.contains("│ start"));
+        assert!(!map.contains("castle_gate"));
+        assert_eq!(state.parse_command("map all"), GameAction::Map(false));
+    }
+}
@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Effect, EffectError, GameEvent, GameState};
+
+/// The run's source of randomness. Its whole state is saved with the game, so a
+/// run replayed from the same seed with the same commands plays out identically.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Rng {
+    /// The seed the run was started with.
+    pub seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// SplitMix64: tiny, fast and the same on every platform and Rust release.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, or 0 if `n` is 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u32
+    }
+
+    /// True `percent` times out of 100.
+    pub fn chance(&mut self, percent: u32) -> bool {
+        percent >= 100 || self.below(100) < percent
+    }
+
+    /// Index of an entry picked with probability proportional to its weight;
+    /// None if all weights are 0.
+    pub fn weighted(&mut self, weights: &[u32]) -> Option<usize> {
+        let total: u64 = weights.iter().map(|&weight| u64::from(weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = ((self.next_u64() as u128 * total as u128) >> 64) as u64;
+        weights.iter().position(|&weight| {
+            let weight = u64::from(weight);
+            if roll < weight {
+                return true;
+            }
+            roll -= weight;
+            false
+        })
+    }
+}
+
+/// One row of a random table, e.g. `{ weight: 3, effects: [!GiveItem coin] }`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Outcome {
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Nothing happens if empty, which is how a table says "usually nothing".
+    #[serde(default)]
+    pub effects: Vec<Effect>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl GameState {
+    /// Starts drawing random numbers from `seed`, e.g. to replay a run.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Picks one of `outcomes` by weight and applies its effects.
+    pub(crate) fn roll_outcome(&mut self, table_id: Option<&str>, outcomes: &[Outcome]) -> Result<(), EffectError> {
+        let weights: Vec<u32> = outcomes.iter().map(|o| o.weight).collect();
+        let Some(index) = self.rng.weighted(&weights) else {
+            return Ok(());
+        };
+        self.emit(GameEvent::Rolled { table_id: table_id.map(str::to_string), outcome: index });
+        self.apply_effects(&outcomes[index].effects)
+    }
+
+    pub(crate) fn roll_table(&mut self, table_id: &str) -> Result<(), EffectError> {
+        let outcomes =
+            self.world.tables.get(table_id).cloned().ok_or_else(|| EffectError::UnknownTable(table_id.to_string()))?;
+        self.roll_outcome(Some(table_id), &outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+    use crate::{AnalysisOptions, GameAction, GameEvent, GameState, ProblemKind, WorldTemplate, analyze};
+
+    const YAML: &str = r#"
+nodes:
+  start:
+    id: start
+    description: "A dusty road."
+    attributes: {}
+    edges:
+      - target_id: road
+        label: "Walk on"
+        conditions: []
+        effects:
+          - !RollTable road_encounters
+    items: []
+  road:
+    id: road
+    description: "More road."
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Walk back"
+        conditions: []
+        effects:
+          - !Random
+            - weight: 1
+              effects:
+                - !GiveItem coin
+            - weight: 0
+              effects:
+                - !AdjustHP -100
+    items: []
+  hideout:
+    id: hideout
+    description: "A bandit hideout."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  coin: { id: coin, name: "Coin", description: "Shiny.", can_pickup: true }
+npcs:
+  bandit:
+    id: bandit
+    name: "Bandit"
+    description: "A masked bandit."
+    node_id: hideout
+tables:
+  road_encounters:
+    - weight: 3
+    - weight: 1
+      effects:
+        - !MoveNpc [bandit, road]
+"#;
+
+    fn walk(seed: u64, steps: usize) -> GameState {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        state.reseed(seed);
+        for _ in 0..steps {
+            let target = if state.player.current_node == "start" { "road" } else { "start" };
+            state.apply_action(GameAction::Move(target.to_string()));
+        }
+        state
+    }
+
+    #[test]
+    fn test_rng_is_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let rolls: Vec<u32> = (0..100).map(|_| a.below(6)).collect();
+        assert_eq!(rolls, (0..100).map(|_| b.below(6)).collect::<Vec<_>>());
+        assert!(rolls.iter().all(|&r| r < 6));
+        assert_ne!(rolls, (0..100).map(|_| Rng::new(43).below(6)).collect::<Vec<_>>());
+
+        let counts = (0..4000).fold([0; 3], |mut counts, _| {
+            counts[a.weighted(&[1, 0, 3]).unwrap()] += 1;
+            counts
+        });
+        assert_eq!(counts[1], 0);
+        assert!((2700..3300).contains(&counts[2]), "{:?}", counts);
+        assert_eq!(a.weighted(&[0, 0]), None);
+        // Weights too large to add up as u32 still work
+        assert!((0..100).all(|_| a.weighted(&[u32::MAX, u32::MAX, 0]).is_some_and(|i| i < 2)));
+    }
+
+    #[test]
+    fn test_seeded_random_effects() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        assert!(!template.validate().iter().any(|d| d.is_error()));
+
+        // Same seed, same story; zero weights never come up
+        let state = walk(7, 40);
+        assert_eq!(state.log, walk(7, 40).log);
+        assert_eq!(state.player.inventory.count("coin"), 20);
+        assert_eq!(state.player.hp, state.player.max_hp);
+        let encounters = (0..20).filter(|&seed| walk(seed, 1).world.npcs["bandit"].node_id == "road").count();
+        assert!(encounters > 0 && encounters < 20);
+        let seed = (0..20).find(|&seed| walk(seed, 1).world.npcs["bandit"].node_id == "road").unwrap();
+        let events = walk(seed, 0).apply_action(GameAction::Move("road".to_string()));
+        assert!(events.contains(&GameEvent::NpcMoved { npc_id: "bandit".to_string(), node_id: "road".to_string() }));
+
+        // The generator travels with the saved game
+        let mut state = walk(7, 4);
+        let json = serde_json::to_string(&state).unwrap();
+        let mut restored: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.rng, state.rng);
+        assert_eq!(restored.rng.seed, 7);
+        let events = state.apply_action(GameAction::Move("road".to_string()));
+        assert_eq!(restored.apply_action(GameAction::Move("road".to_string())), events);
+        assert!(events.iter().any(|e| matches!(e, GameEvent::Rolled { table_id: Some(_), .. })));
+
+        // Undo rewinds the generator too, so retrying gives the same roll
+        let rng = state.rng;
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Undo(1));
+        state.apply_action(GameAction::Move("start".to_string()));
+        state.apply_action(GameAction::Undo(1));
+        assert_eq!(state.rng, rng);
+    }
+
+    #[test]
+    fn test_analysis_reports_random_outcomes() {
+        let template = WorldTemplate::from_yaml(YAML).unwrap();
+        // Every walk back adds a coin, so the search never ends on its own
+        let report = analyze(&template, &AnalysisOptions { max_states: 100, ..AnalysisOptions::default() });
+        let problem = report.problems.iter().find(|p| p.kind == ProblemKind::RandomOutcome).unwrap();
+        assert_eq!(problem.witness, Some(vec![GameAction::Move("road".to_string())]));
+    }
+}
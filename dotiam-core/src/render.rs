@@ -0,0 +1,251 @@
+use crate::{EffectError, GameEvent, ItemStack, ItemStacks, World};
+
+/// Turns game events into log lines. Implement this to translate the game
+/// into another language or to format the log for a different frontend.
+pub trait EventRenderer {
+    fn render(&self, world: &World, event: &GameEvent) -> Vec<String>;
+}
+
+/// The default renderer used for `GameState::log`.
+pub struct EnglishRenderer;
+
+fn name(world: &World, item_id: &str) -> String {
+    world.items.get(item_id).map_or(item_id.to_string(), |i| i.name.clone())
+}
+
+fn npc_name(world: &World, npc_id: &str) -> String {
+    world.npcs.get(npc_id).map_or(npc_id.to_string(), |n| n.name.clone())
+}
+
+/// The item name, prefixed with the count when there is more than one.
+fn counted(world: &World, item_id: &str, count: u32) -> String {
+    match count {
+        1 => name(world, item_id),
+        _ => format!("{}x {}", count, name(world, item_id)),
+    }
+}
+
+/// Item lists as shown by `look` and `inventory`, with any item state in brackets.
+fn names(world: &World, items: &ItemStacks) -> String {
+    let described = |stack: &ItemStack| match world.items.get(&stack.item_id).and_then(|i| i.state_label(stack)) {
+        Some(state) => format!("{} ({})", counted(world, &stack.item_id, stack.count), state),
+        None => counted(world, &stack.item_id, stack.count),
+    };
+    items.iter().map(described).collect::<Vec<_>>().join(", ")
+}
+
+impl EventRenderer for EnglishRenderer {
+    fn render(&self, world: &World, event: &GameEvent) -> Vec<String> {
+        let line = match event {
+            GameEvent::HelpShown => {
+                return vec![
+                    "Available commands:".to_string(),
+                    "  h, help          - Show this help".to_string(),
+                    "  l, look          - Look at the current scene".to_string(),
+                    "  g, go <target>   - Go to a specific place".to_string(),
+                    "  x, explore [obj] - Explore the scene or an object".to_string(),
+                    "  c, combine <a> <b> [<c> ...] - Combine items into something new".to_string(),
+                    "  p, pickup [n] <item> - Pick up an item, or n of them".to_string(),
+                    "  d, drop [n] <item> - Drop an item, or n of them".to_string(),
+                    "  open/close <container> - Open or close a chest, bag, ...".to_string(),
+                    "  put <item> in <container>, take <item> from <container>".to_string(),
+                    "  i, inventory     - Show your inventory".to_string(),
+                    "  u, use <item> [on <target>] - Use an item, optionally on something".to_string(),
+                    "  t, talk <npc>    - Talk to someone; answer with the number of a choice".to_string(),
+                    "  attack <npc> [with <item>] - Fight someone, with your best weapon by default".to_string(),
+                    "  flee             - Run back the way you came during a fight".to_string(),
+                    "  m, map [all]     - Show a map of visited places, or of everything".to_string(),
+                    "  undo [n]         - Take back the last n turns".to_string(),
+                ];
+            }
+            GameEvent::Looked { description, items, npcs, paths, .. } => {
+                let mut lines = vec![description.clone()];
+                if !items.is_empty() {
+                    lines.push(format!("Items here: {}", names(world, items)));
+                }
+                if !npcs.is_empty() {
+                    let npcs: Vec<String> = npcs.iter().map(|id| npc_name(world, id)).collect();
+                    lines.push(format!("You see: {}", npcs.join(", ")));
+                }
+                lines.push(format!("Available paths: {}", paths.join(", ")));
+                return lines;
+            }
+            GameEvent::Moved { label, .. } => format!("You move to: {}.", label),
+            GameEvent::MoveBlocked { label, .. } => format!("You cannot go to {}, conditions not met.", label),
+            GameEvent::NoPath(_) => "You cannot go that way.".to_string(),
+            GameEvent::Teleported(node_id) => format!("You are whisked away to {}.", node_id),
+            GameEvent::ItemExamined(item_id) => match world.items.get(item_id) {
+                Some(item) => format!("{}: {}", item.name, item.description),
+                None => format!("You don't see anything special about {}.", item_id),
+            },
+            GameEvent::ItemNotVisible(item_id) => format!("You don't see any {} here.", item_id),
+            GameEvent::FeatureExamined { description, .. } => description.clone(),
+            GameEvent::NothingSpecial(target) => format!("You don't see anything special about {}.", target),
+            GameEvent::NothingFound => "You look around carefully but find nothing new.".to_string(),
+            GameEvent::EdgeDiscovered { message: Some(message), .. } | GameEvent::ItemDiscovered { message: Some(message), .. } => {
+                message.clone()
+            }
+            GameEvent::EdgeDiscovered { label, .. } => format!("You discover a hidden way: {}.", label),
+            GameEvent::ItemDiscovered { item_id, .. } => format!("You find: {}", name(world, item_id)),
+            GameEvent::ItemPickedUp(item_id, count) => format!("You picked up: {}", counted(world, item_id, *count)),
+            GameEvent::CannotPickUp(_) => "You cannot pick that up.".to_string(),
+            GameEvent::TooHeavy { item_id, carried, max } => {
+                format!("The {} is too heavy to carry as well. You are carrying {} of at most {}.", name(world, item_id), carried, max)
+            }
+            GameEvent::InventoryFull { item_id, max_slots } => {
+                format!("You have no room for the {}. All {} slots are in use.", name(world, item_id), max_slots)
+            }
+            GameEvent::ItemNotHere(_) => "That item is not here.".to_string(),
+            GameEvent::ItemDropped(item_id, count) => format!("You dropped: {}", counted(world, item_id, *count)),
+            GameEvent::NotEnough { item_id, available } => {
+                format!("There are only {}.", counted(world, item_id, *available))
+            }
+            GameEvent::NotCarrying(_) => "You don't have that item.".to_string(),
+            GameEvent::InventoryShown(items) if items.is_empty() => "Your inventory is empty.".to_string(),
+            GameEvent::InventoryShown(items) => format!("You are carrying: {}", names(world, items)),
+            GameEvent::MapShown(map) => map.clone(),
+            GameEvent::ContainerOpened { container_id, contents } if contents.is_empty() => {
+                format!("You open the {}. It is empty.", name(world, container_id))
+            }
+            GameEvent::ContainerOpened { container_id, contents } => {
+                format!("You open the {}. Inside you see: {}", name(world, container_id), names(world, contents))
+            }
+            GameEvent::ContainerSearched { container_id, contents } if contents.is_empty() => {
+                format!("The {} is empty.", name(world, container_id))
+            }
+            GameEvent::ContainerSearched { container_id, contents } => {
+                format!("The {} contains: {}", name(world, container_id), names(world, contents))
+            }
+            GameEvent::ContainerClosed(container_id) => format!("You close the {}.", name(world, container_id)),
+            GameEvent::ContainerLocked(container_id) => format!("The {} is locked.", name(world, container_id)),
+            GameEvent::ContainerUnlocked(container_id) => format!("You unlock the {}.", name(world, container_id)),
+            GameEvent::ContainerIsClosed(container_id) => format!("The {} is closed.", name(world, container_id)),
+            GameEvent::AlreadyOpen(container_id) => format!("The {} is already open.", name(world, container_id)),
+            GameEvent::AlreadyClosed(container_id) => format!("The {} is already closed.", name(world, container_id)),
+            GameEvent::NotAContainer(item_id) => format!("The {} cannot hold anything.", name(world, item_id)),
+            GameEvent::ItemPutIn { item_id, container_id, count } => {
+                format!("You put {} in the {}.", counted(world, item_id, *count), name(world, container_id))
+            }
+            GameEvent::CannotPutInside { item_id, container_id } => {
+                format!("You cannot put the {} inside the {}.", name(world, item_id), name(world, container_id))
+            }
+            GameEvent::ItemTakenFrom { item_id, container_id, count } => {
+                format!("You take {} from the {}.", counted(world, item_id, *count), name(world, container_id))
+            }
+            GameEvent::NotInContainer { item_id, container_id } => {
+                format!("There is no {} in the {}.", item_id, name(world, container_id))
+            }
+            GameEvent::Combined { message: Some(message), .. } => message.clone(),
+            GameEvent::Combined { results, .. } => format!("You combined them and created: {}!", names(world, results)),
+            GameEvent::CannotCombine(_) => "Those items cannot be combined.".to_string(),
+            GameEvent::MissingIngredients(item_ids) => {
+                let missing: Vec<String> = item_ids.iter().map(|id| name(world, id)).collect();
+                format!("You need more for that: {}.", missing.join(", "))
+            }
+            GameEvent::CombineBlocked(_) => "You can't make that here and now.".to_string(),
+            GameEvent::ItemUsed { message: Some(message), .. } => message.clone(),
+            GameEvent::ItemUsed { item_id, .. } => format!("You use the {}.", name(world, item_id)),
+            GameEvent::FeatureUsed { message: Some(message), .. } => message.clone(),
+            GameEvent::FeatureUsed { feature_id, .. } => format!("You use the {}.", feature_id),
+            GameEvent::UseBlocked { item_id, .. } => format!("You can't use the {} like that yet.", name(world, item_id)),
+            GameEvent::NothingHappens { item_id, target: Some(target) } => {
+                format!("You use the {} on {}. Nothing obvious happens.", name(world, item_id), target)
+            }
+            GameEvent::NothingHappens { item_id, target: None } => {
+                format!("You use the {}. Nothing obvious happens.", name(world, item_id))
+            }
+            GameEvent::Message(message) => message.clone(),
+            GameEvent::HazardHit { message: Some(message), damage } => format!("{} You lose {} HP.", message, damage),
+            GameEvent::HazardHit { damage, .. } => format!("Ouch! You lose {} HP.", damage),
+            GameEvent::ItemConsumed { item_id, healed: 0 } => format!("You consume the {}.", name(world, item_id)),
+            GameEvent::ItemConsumed { item_id, healed } => {
+                format!("You consume the {} and recover {} HP.", name(world, item_id), healed)
+            }
+            GameEvent::NoNeedToConsume(item_id) => {
+                format!("You are in perfect health. Better save the {} for later.", name(world, item_id))
+            }
+            GameEvent::StatusRemoved(status_id) => format!("The {} wears off.", status_id),
+            GameEvent::StatusTicked { status_id, from, to } if to < from => {
+                format!("The {} costs you {} HP.", status_id, from - to)
+            }
+            GameEvent::StatusTicked { status_id, from, to } => format!("The {} restores {} HP.", status_id, to - from),
+            GameEvent::NpcExamined(npc_id) => {
+                world.npcs.get(npc_id).map_or(npc_id.clone(), |n| n.description.clone())
+            }
+            GameEvent::NpcNotHere(npc_id) => format!("There is no {} here.", npc_name(world, npc_id)),
+            GameEvent::NothingToSay(npc_id) => format!("{} has nothing to say.", npc_name(world, npc_id)),
+            GameEvent::DialogueLine { npc_id, text, choices } => {
+                let mut lines = vec![format!("{}: \"{}\"", npc_name(world, npc_id), text)];
+                lines.extend(choices.iter().enumerate().map(|(i, choice)| format!("  {}. {}", i + 1, choice)));
+                return lines;
+            }
+            GameEvent::ChoiceMade { text, .. } => format!("You: \"{}\"", text),
+            GameEvent::NoSuchChoice(number) => format!("There is no choice {}.", number),
+            GameEvent::NotTalking => "You are not talking to anyone.".to_string(),
+            GameEvent::ConversationEnded(npc_id) => format!("You finish talking to {}.", npc_name(world, npc_id)),
+            GameEvent::Attacked { npc_id, weapon, damage, target_hp } => {
+                let with = weapon.as_ref().map_or("your fists".to_string(), |id| format!("the {}", name(world, id)));
+                match (damage, target_hp) {
+                    (0, _) => format!("You swing {} at {}, but the blow glances off.", with, npc_name(world, npc_id)),
+                    (_, 0) => format!("You strike {} with {} for {} damage.", npc_name(world, npc_id), with, damage),
+                    _ => format!(
+                        "You strike {} with {} for {} damage. ({} HP left)",
+                        npc_name(world, npc_id),
+                        with,
+                        damage,
+                        target_hp
+                    ),
+                }
+            }
+            GameEvent::EnemyAttacked { npc_id, damage: 0, .. } => {
+                format!("{} attacks, but you fend it off.", npc_name(world, npc_id))
+            }
+            GameEvent::EnemyAttacked { npc_id, damage, hp } => {
+                format!("{} hits you for {} damage. ({} HP left)", npc_name(world, npc_id), damage, hp)
+            }
+            GameEvent::EnemyDefeated { npc_id, loot } if loot.is_empty() => format!("{} is defeated!", npc_name(world, npc_id)),
+            GameEvent::EnemyDefeated { npc_id, loot } => {
+                format!("{} is defeated! It leaves behind: {}.", npc_name(world, npc_id), names(world, loot))
+            }
+            GameEvent::CannotAttack(npc_id) => format!("You have no reason to fight {}.", npc_name(world, npc_id)),
+            GameEvent::Fled(_) => "You turn and flee!".to_string(),
+            GameEvent::NothingToFlee => "Nothing here is fighting you.".to_string(),
+            GameEvent::NoEscape => "There is no way out!".to_string(),
+            GameEvent::CheckpointReached(_) => "This feels like a safe place to return to.".to_string(),
+            GameEvent::Respawned(_) => "Everything goes dark... You wake up back at the last safe place.".to_string(),
+            GameEvent::AttributeChanged { .. }
+            | GameEvent::HpChanged { .. }
+            | GameEvent::ItemReceived(_)
+            | GameEvent::ItemRemoved(_)
+            | GameEvent::FlagChanged { .. }
+            | GameEvent::ItemStateChanged { .. }
+            | GameEvent::StatusAdded(_)
+            | GameEvent::Rolled { .. }
+            | GameEvent::ItemSpawned { .. }
+            | GameEvent::EdgeAdded { .. }
+            | GameEvent::EdgeRemoved { .. }
+            | GameEvent::DescriptionChanged(_)
+            | GameEvent::NpcMoved { .. }
+            | GameEvent::NpcAttributeChanged { .. } => return vec![],
+            GameEvent::EffectFailed(EffectError::MissingItem(item_id)) => {
+                format!("You need the {} for that.", name(world, item_id))
+            }
+            GameEvent::EffectFailed(EffectError::UnknownNode(node_id)) => format!("Unknown node: {}", node_id),
+            GameEvent::EffectFailed(EffectError::UnknownItem(item_id)) => format!("Unknown item: {}", item_id),
+            GameEvent::EffectFailed(EffectError::UnknownNpc(npc_id)) => format!("Unknown character: {}", npc_id),
+            GameEvent::EffectFailed(EffectError::UnknownTable(table_id)) => format!("Unknown table: {}", table_id),
+            GameEvent::EffectFailed(EffectError::NoSuchEdge(node_id, target_id)) => {
+                format!("There is no path from {} to {}.", node_id, target_id)
+            }
+            GameEvent::EffectFailed(EffectError::TeleportLoop(node_id)) => {
+                format!("Teleports keep leading to teleports, the last one to {}.", node_id)
+            }
+            GameEvent::GameEnded { message, .. } => message.clone(),
+            GameEvent::GameIsOver => "The game is over.".to_string(),
+            GameEvent::Undone(steps) => format!("You rewind time by {} turn(s).", steps),
+            GameEvent::NothingToUndo => "There is nothing to undo.".to_string(),
+            GameEvent::UnknownCommand(cmd) => format!("Unknown command: {}", cmd),
+        };
+        vec![line]
+    }
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::analysis::StateKeys;
+use crate::{AnalysisOptions, GameAction, GameState, GameStatus, WorldTemplate};
+
+/// What a walkthrough has to achieve.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Goal {
+    ReachNode(String),
+    HoldItem(String),
+    Victory,
+}
+
+impl Goal {
+    pub fn is_met(&self, state: &GameState) -> bool {
+        match self {
+            Goal::ReachNode(node_id) => state.player.current_node == *node_id,
+            Goal::HoldItem(item_id) => state.player.inventory.contains(item_id),
+            Goal::Victory => state.status == GameStatus::Won,
+        }
+    }
+}
+
+/// A shortest walkthrough, both as actions and as commands a player could type.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Solution {
+    pub actions: Vec<GameAction>,
+    pub commands: Vec<String>,
+}
+
+/// Breadth-first search from the start of `template` for the fewest actions that
+/// meet `goal`. Returns `None` when the goal cannot be met within
+/// `options.max_states` explored states.
+pub fn solve(template: &WorldTemplate, goal: &Goal, options: &AnalysisOptions) -> Option<Solution> {
+    let start = GameState::new_with_world("Solver".to_string(), template.to_world()).search_root();
+    if goal.is_met(&start) {
+        return Some(Solution { actions: Vec::new(), commands: Vec::new() });
+    }
+
+    let keys = StateKeys::new(&start);
+    let mut parents: Vec<Option<(usize, GameAction)>> = vec![None];
+    let mut index_of: HashMap<String, usize> = HashMap::from([(keys.key(&start), 0)]);
+    let mut queue = VecDeque::from([(0, start)]);
+
+    while let Some((index, state)) = queue.pop_front() {
+        if state.is_over() {
+            continue;
+        }
+
+        for action in state.search_actions(options) {
+            let mut next = state.clone();
+            next.apply_action(action.clone());
+            next.log.clear();
+
+            let key = keys.key(&next);
+            if index_of.contains_key(&key) || parents.len() >= options.max_states {
+                continue;
+            }
+            index_of.insert(key, parents.len());
+            parents.push(Some((index, action)));
+
+            if goal.is_met(&next) {
+                let mut actions = Vec::new();
+                let mut current = parents.len() - 1;
+                while let Some((parent, action)) = &parents[current] {
+                    actions.push(action.clone());
+                    current = *parent;
+                }
+                actions.reverse();
+                let commands = actions.iter().map(GameAction::to_command).collect();
+                return Some(Solution { actions, commands });
+            }
+            queue.push_back((parents.len() - 1, next));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn whispering_woods() -> WorldTemplate {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml")
+    }
+
+    #[test]
+    fn test_solve_small_world() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A courtyard."
+    attributes: {}
+    edges:
+      - target_id: shed
+        label: "Shed"
+        conditions: []
+      - target_id: gate
+        label: "Gate"
+        conditions:
+          - !HasItem key
+    items: [rake]
+  shed:
+    id: shed
+    description: "A shed."
+    attributes: {}
+    edges:
+      - target_id: start
+        label: "Courtyard"
+        conditions: []
+    items: [key]
+  gate:
+    id: gate
+    description: "The gate."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  key: { id: key, name: "Key", description: "A key.", can_pickup: true }
+  rake: { id: rake, name: "Rake", description: "A rake.", can_pickup: true }
+victory:
+  - conditions:
+      - !AtNode gate
+    message: "You are out."
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let solution = solve(&template, &Goal::Victory, &AnalysisOptions::default()).unwrap();
+        assert_eq!(solution.commands.len(), 4);
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        for command in &solution.commands {
+            let action = state.parse_command(command);
+            state.apply_action(action);
+        }
+        assert_eq!(state.status, GameStatus::Won);
+
+        assert_eq!(solve(&template, &Goal::HoldItem("no_such_item".to_string()), &AnalysisOptions::default()), None);
+    }
+
+    #[test]
+    fn test_solve_whispering_woods() {
+        let template = whispering_woods();
+        // Bounded so that a world grown past what the default run can afford fails
+        // here instead of slowing every test run down
+        let options = AnalysisOptions { max_states: 10_000, ..AnalysisOptions::default() };
+        let solution = solve(&template, &Goal::Victory, &options).expect("world.yaml is solvable");
+
+        // The commands replay to a win through the normal text interface
+        let mut state = GameState::new_with_world("Tester".to_string(), template.to_world());
+        for command in &solution.commands {
+            let action = state.parse_command(command);
+            state.apply_action(action);
+        }
+        assert_eq!(state.status, GameStatus::Won);
+        assert_eq!(solution.commands.len(), solution.actions.len());
+        assert!(solution.commands.contains(&"use iron_key on door".to_string()));
+    }
+
+    #[test]
+    #[ignore = "explores all of world.yaml; run with `cargo test -- --ignored`"]
+    fn test_solve_whispering_woods_impossible_goal() {
+        let template = whispering_woods();
+        assert_eq!(solve(&template, &Goal::HoldItem("no_such_item".to_string()), &AnalysisOptions::default()), None);
+    }
+
+    #[test]
+    fn test_solve_intermediate_goals() {
+        let template = whispering_woods();
+        let options = AnalysisOptions::default();
+
+        assert_eq!(solve(&template, &Goal::ReachNode("start".to_string()), &options).unwrap().actions, vec![]);
+
+        let solution = solve(&template, &Goal::HoldItem("torch".to_string()), &options).unwrap();
+        assert_eq!(solution.actions.last(), Some(&GameAction::Combine(vec!["flint".to_string(), "dry_wood".to_string()])));
+    }
+}
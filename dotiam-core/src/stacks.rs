@@ -0,0 +1,331 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize};
+
+use crate::Container;
+
+/// A number of identical items, e.g. 5 coins. Units of the same item only share
+/// a stack while nothing sets them apart, like different contents of two bags or
+/// one lit lamp among unlit ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StackEntry", into = "StackEntry")]
+pub struct ItemStack {
+    pub item_id: String,
+    pub count: u32,
+    /// The container of every unit in the stack, once it no longer matches the
+    /// item's definition.
+    pub container: Option<Container>,
+    /// The state of every unit in the stack where it differs from the item's
+    /// definition, e.g. `lit: "true"` for lamps that were lit.
+    pub state: BTreeMap<String, String>,
+}
+
+impl ItemStack {
+    pub fn new(item_id: impl Into<String>, count: u32) -> Self {
+        Self { item_id: item_id.into(), count, container: None, state: BTreeMap::new() }
+    }
+
+    /// Whether units of `other` can join this stack.
+    fn stacks_with(&self, other: &ItemStack) -> bool {
+        self.item_id == other.item_id && self.container == other.container && self.state == other.state
+    }
+}
+
+impl fmt::Display for ItemStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 1 {
+            write!(f, "{}", self.item_id)
+        } else {
+            write!(f, "{}x {}", self.count, self.item_id)
+        }
+    }
+}
+
+/// How a stack is written: a plain id for a single item, so that item lists
+/// written before stacks existed still load.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum StackEntry {
+    Single(String),
+    Counted {
+        item_id: String,
+        count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        container: Option<Container>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        state: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct CountedFields {
+    item_id: String,
+    count: u32,
+    #[serde(default)]
+    container: Option<Container>,
+    #[serde(default)]
+    state: BTreeMap<String, String>,
+}
+
+impl<'de> Deserialize<'de> for StackEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // `#[serde(untagged)]` cannot be used: it buffers the input, which loses the
+        // YAML tags of a container's unlock conditions.
+        struct EntryVisitor;
+
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = StackEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an item id, or a map with item_id and count")
+            }
+
+            fn visit_str<E: de::Error>(self, item_id: &str) -> Result<StackEntry, E> {
+                Ok(StackEntry::Single(item_id.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<StackEntry, A::Error> {
+                let fields = CountedFields::deserialize(MapAccessDeserializer::new(map))?;
+                let CountedFields { item_id, count, container, state } = fields;
+                Ok(StackEntry::Counted { item_id, count, container, state })
+            }
+        }
+
+        deserializer.deserialize_any(EntryVisitor)
+    }
+}
+
+impl From<StackEntry> for ItemStack {
+    fn from(entry: StackEntry) -> Self {
+        match entry {
+            StackEntry::Single(item_id) => Self::new(item_id, 1),
+            StackEntry::Counted { item_id, count, container, state } => Self { item_id, count, container, state },
+        }
+    }
+}
+
+impl From<ItemStack> for StackEntry {
+    fn from(stack: ItemStack) -> Self {
+        match stack {
+            ItemStack { item_id, count: 1, container: None, state } if state.is_empty() => StackEntry::Single(item_id),
+            ItemStack { item_id, count, container, state } => StackEntry::Counted { item_id, count, container, state },
+        }
+    }
+}
+
+/// The items in an inventory, a node or a container, in the order they arrived.
+/// Units of the same item share a stack unless their container contents or state
+/// differ, so one item id can have several stacks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<ItemStack>", into = "Vec<ItemStack>")]
+pub struct ItemStacks(Vec<ItemStack>);
+
+impl ItemStacks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Units of `item_id` over all its stacks, saturating like `insert` does.
+    pub fn count(&self, item_id: &str) -> u32 {
+        self.0.iter().filter(|s| s.item_id == item_id).fold(0, |total, s| total.saturating_add(s.count))
+    }
+
+    /// Units of every item, saturating like `insert` does.
+    pub fn total(&self) -> u32 {
+        self.0.iter().fold(0, |total, s| total.saturating_add(s.count))
+    }
+
+    pub fn contains(&self, item_id: &str) -> bool {
+        self.count(item_id) > 0
+    }
+
+    /// Adds `count` units just like the item's definition.
+    pub fn add(&mut self, item_id: &str, count: u32) {
+        self.insert(ItemStack::new(item_id, count));
+    }
+
+    /// Adds a stack, joining an existing one if nothing sets their units apart.
+    pub fn insert(&mut self, stack: ItemStack) {
+        if stack.count == 0 {
+            return;
+        }
+        match self.0.iter_mut().find(|s| s.stacks_with(&stack)) {
+            Some(existing) => existing.count = existing.count.saturating_add(stack.count),
+            None => self.0.push(stack),
+        }
+    }
+
+    pub fn append(&mut self, stacks: ItemStacks) {
+        for stack in stacks.0 {
+            self.insert(stack);
+        }
+    }
+
+    /// Takes `count` units away, first from the stacks that arrived first, and
+    /// returns them. Returns None and changes nothing if there are fewer.
+    pub fn take(&mut self, item_id: &str, count: u32) -> Option<ItemStacks> {
+        if self.count(item_id) < count {
+            return None;
+        }
+        let mut taken = ItemStacks::new();
+        let mut left = count;
+        for stack in self.0.iter_mut().filter(|s| s.item_id == item_id) {
+            let units = stack.count.min(left);
+            stack.count -= units;
+            left -= units;
+            taken.insert(ItemStack { count: units, ..stack.clone() });
+        }
+        self.0.retain(|s| s.count > 0);
+        Some(taken)
+    }
+
+    /// Takes every unit of an item away and returns them.
+    pub fn take_all(&mut self, item_id: &str) -> ItemStacks {
+        let (taken, kept) = std::mem::take(&mut self.0).into_iter().partition(|s| s.item_id == item_id);
+        self.0 = kept;
+        ItemStacks(taken)
+    }
+
+    /// Takes `count` units away. Returns false and changes nothing if there are fewer.
+    pub fn remove(&mut self, item_id: &str, count: u32) -> bool {
+        self.take(item_id, count).is_some()
+    }
+
+    /// Removes the whole stack and returns how many there were.
+    pub fn remove_all(&mut self, item_id: &str) -> u32 {
+        self.take_all(item_id).total()
+    }
+
+    /// One unit of the stack at `index`, split off into a stack of its own right
+    /// after it so that it can change without the others.
+    pub fn single_mut(&mut self, index: usize) -> &mut ItemStack {
+        if self.0[index].count > 1 {
+            self.0[index].count -= 1;
+            let unit = ItemStack { count: 1, ..self.0[index].clone() };
+            self.0.insert(index + 1, unit);
+            return &mut self.0[index + 1];
+        }
+        &mut self.0[index]
+    }
+
+    /// Joins stacks whose units have become alike again.
+    pub fn regroup(&mut self) {
+        *self = Self::from(std::mem::take(&mut self.0));
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ItemStack> {
+        self.0.get(index)
+    }
+
+    /// Every item id once, in the order they first arrived.
+    pub fn ids(&self) -> impl Iterator<Item = &String> {
+        let stacks = &self.0;
+        stacks.iter().enumerate().filter(|(i, s)| !stacks[..*i].iter().any(|o| o.item_id == s.item_id)).map(|(_, s)| &s.item_id)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ItemStack> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of stacks, not of units.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Orders the stacks by item id, for comparisons that should ignore arrival order.
+    pub fn sort(&mut self) {
+        self.0.sort_by(|a, b| a.item_id.cmp(&b.item_id));
+    }
+}
+
+impl From<Vec<ItemStack>> for ItemStacks {
+    fn from(stacks: Vec<ItemStack>) -> Self {
+        let mut merged = Self::new();
+        for stack in stacks {
+            merged.insert(stack);
+        }
+        merged
+    }
+}
+
+impl From<ItemStacks> for Vec<ItemStack> {
+    fn from(stacks: ItemStacks) -> Self {
+        stacks.0
+    }
+}
+
+/// One unit per id, e.g. `["torch", "flint"].into_iter().collect()`.
+impl<S: AsRef<str>> FromIterator<S> for ItemStacks {
+    fn from_iter<I: IntoIterator<Item = S>>(ids: I) -> Self {
+        let mut stacks = Self::new();
+        for id in ids {
+            stacks.add(id.as_ref(), 1);
+        }
+        stacks
+    }
+}
+
+impl<'a> IntoIterator for &'a ItemStacks {
+    type Item = &'a ItemStack;
+    type IntoIter = std::slice::Iter<'a, ItemStack>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_ids_and_counted_stacks_load() {
+        let stacks: ItemStacks = serde_yaml::from_str("[arrow, {item_id: coin, count: 5}, arrow]").unwrap();
+        assert_eq!(stacks.count("arrow"), 2);
+        assert_eq!(stacks.count("coin"), 5);
+        assert_eq!(stacks.len(), 2);
+
+        // Single items are written back as plain ids
+        let json = serde_json::to_string(&stacks).unwrap();
+        assert_eq!(json, r#"[{"item_id":"arrow","count":2},{"item_id":"coin","count":5}]"#);
+        let single: ItemStacks = ["torch"].into_iter().collect();
+        assert_eq!(serde_json::to_string(&single).unwrap(), r#"["torch"]"#);
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut stacks = ItemStacks::new();
+        stacks.add("coin", 3);
+        stacks.add("coin", 2);
+        assert!(!stacks.remove("coin", 6));
+        assert!(stacks.remove("coin", 4));
+        assert_eq!(stacks.count("coin"), 1);
+        assert_eq!(stacks.remove_all("coin"), 1);
+        assert!(stacks.is_empty());
+    }
+
+    #[test]
+    fn test_counts_over_several_stacks_saturate() {
+        let mut stacks = ItemStacks::new();
+        let mut lit = ItemStack::new("lamp", u32::MAX);
+        lit.state.insert("lit".to_string(), "true".to_string());
+        stacks.insert(ItemStack::new("lamp", u32::MAX));
+        stacks.insert(lit);
+        stacks.add("coin", 1);
+        assert_eq!(stacks.len(), 3);
+        assert_eq!(stacks.count("lamp"), u32::MAX);
+        assert_eq!(stacks.total(), u32::MAX);
+
+        // Every stack goes, not only as many units as the count could say
+        assert_eq!(stacks.remove_all("lamp"), u32::MAX);
+        assert!(!stacks.contains("lamp"));
+        assert_eq!(stacks.total(), 1);
+    }
+}
@@ -0,0 +1,797 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::map::MAX_POSITION;
+use crate::{Condition, ConditionType, Effect, Feature, Outcome, Trigger, UseTarget, WorldTemplate};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    MissingStartNode,
+    IdMismatch { expected: String, found: String },
+    UnknownNode(String),
+    UnknownItem(String),
+    UnknownNpc(String),
+    UnknownDialogueNode(String),
+    UnknownTable(String),
+    /// A feature name that no feature answers to by id or alias; for hidden
+    /// things that is a feature of their node, for use rules one of any node.
+    UnknownFeature(String),
+    /// `Not` wraps a list for serde_yaml's sake but must hold exactly one condition.
+    NotArity(usize),
+    /// A random effect or table whose outcomes all have weight 0.
+    NothingToRoll,
+    DuplicateEdge(String),
+    DuplicateCombination,
+    NoIngredients,
+    UnusedItem(String),
+    EmptyEnding,
+    /// A container whose definition ends up inside itself, e.g. `[bag, box, bag]`.
+    ContainerCycle(Vec<String>),
+    /// Nodes whose enter triggers teleport on around to the first again, e.g. `[a, b, a]`.
+    TeleportCycle(Vec<String>),
+    /// A map `position` further than `MAX_POSITION` from the origin; the map clamps it.
+    PositionOutOfRange(i32, i32),
+}
+
+/// A problem found by `WorldTemplate::validate`, located by a path such as
+/// `nodes.forest.edges[0].target_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub path: String,
+    pub kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::MissingStartNode => write!(f, "there is no `start` node"),
+            DiagnosticKind::IdMismatch { expected, found } => {
+                write!(f, "id `{}` does not match its key `{}`", found, expected)
+            }
+            DiagnosticKind::UnknownNode(id) => write!(f, "unknown node `{}`", id),
+            DiagnosticKind::UnknownItem(id) => write!(f, "unknown item `{}`", id),
+            DiagnosticKind::UnknownNpc(id) => write!(f, "unknown npc `{}`", id),
+            DiagnosticKind::UnknownDialogueNode(id) => write!(f, "unknown dialogue node `{}`", id),
+            DiagnosticKind::UnknownTable(id) => write!(f, "unknown table `{}`", id),
+            DiagnosticKind::UnknownFeature(name) => write!(f, "unknown feature `{}`", name),
+            DiagnosticKind::NotArity(found) => {
+                write!(f, "`Not` takes exactly one condition but has {}; wrap several in `All` or `Any`", found)
+            }
+            DiagnosticKind::NothingToRoll => write!(f, "no outcome has a weight above 0, so nothing ever happens"),
+            DiagnosticKind::DuplicateEdge(target) => {
+                write!(f, "another edge already leads to `{}`, this one can never be taken", target)
+            }
+            DiagnosticKind::DuplicateCombination => write!(f, "the same items are already combined by an earlier combination"),
+            DiagnosticKind::NoIngredients => write!(f, "combination has no ingredients and can never be made"),
+            DiagnosticKind::UnusedItem(id) => write!(f, "item `{}` is never placed in a node or created", id),
+            DiagnosticKind::EmptyEnding => write!(f, "ending has no conditions and fires immediately"),
+            DiagnosticKind::ContainerCycle(chain) => write!(f, "container holds itself: {}", chain.join(" -> ")),
+            DiagnosticKind::TeleportCycle(chain) => {
+                write!(f, "entering it can teleport around in a loop: {}", chain.join(" -> "))
+            }
+            DiagnosticKind::PositionOutOfRange(x, y) => {
+                write!(f, "position [{}, {}] is more than {} cells from the origin", x, y, MAX_POSITION)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.path, self.kind)
+    }
+}
+
+struct Validator<'a> {
+    template: &'a WorldTemplate,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn report(&mut self, severity: Severity, path: String, kind: DiagnosticKind) {
+        self.diagnostics.push(Diagnostic { severity, path, kind });
+    }
+
+    fn node(&mut self, path: String, node_id: &str) {
+        if !self.template.nodes.contains_key(node_id) {
+            self.report(Severity::Error, path, DiagnosticKind::UnknownNode(node_id.to_string()));
+        }
+    }
+
+    fn item(&mut self, path: String, item_id: &str) {
+        if !self.template.items.contains_key(item_id) {
+            self.report(Severity::Error, path, DiagnosticKind::UnknownItem(item_id.to_string()));
+        }
+    }
+
+    fn npc(&mut self, path: String, npc_id: &str) {
+        if !self.template.npcs.contains_key(npc_id) {
+            self.report(Severity::Error, path, DiagnosticKind::UnknownNpc(npc_id.to_string()));
+        }
+    }
+
+    fn feature<'f>(&mut self, path: String, mut features: impl Iterator<Item = &'f Feature>, name: &str) {
+        if !features.any(|f| f.is_called(name)) {
+            self.report(Severity::Error, path, DiagnosticKind::UnknownFeature(name.to_string()));
+        }
+    }
+
+    fn conditions(&mut self, path: &str, conditions: &[Condition]) {
+        for (i, condition) in conditions.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match &condition.condition_type {
+                ConditionType::HasItem(item_id) | ConditionType::HasSeen(item_id) | ConditionType::ItemState(item_id, _, _) => {
+                    self.item(path, item_id)
+                }
+                ConditionType::HasVisited(node_id) | ConditionType::AtNode(node_id) => self.node(path, node_id),
+                ConditionType::ItemAt(item_id, node_id) => {
+                    self.item(path.clone(), item_id);
+                    self.node(path, node_id);
+                }
+                ConditionType::NodeAttribute(node_id, _, _) => self.node(path, node_id),
+                ConditionType::NpcAttribute(npc_id, _, _) | ConditionType::NpcDefeated(npc_id) => self.npc(path, npc_id),
+                ConditionType::EdgeDiscovered(node_id, target_id) => {
+                    self.node(path.clone(), node_id);
+                    self.node(path, target_id);
+                }
+                ConditionType::Not(nested) if nested.len() != 1 => {
+                    self.report(Severity::Error, path.clone(), DiagnosticKind::NotArity(nested.len()));
+                    self.conditions(&path, nested)
+                }
+                ConditionType::All(nested) | ConditionType::Any(nested) | ConditionType::Not(nested) => {
+                    self.conditions(&path, nested)
+                }
+                ConditionType::HasAttribute(_, _)
+                | ConditionType::MinHP(_)
+                | ConditionType::MaxHP(_)
+                | ConditionType::AttributeCompare(_, _, _)
+                | ConditionType::CarriedWeight(_, _)
+                | ConditionType::HasStatus(_)
+                | ConditionType::TurnAtLeast(_)
+                | ConditionType::TurnBefore(_)
+                | ConditionType::WorldFlag(_, _) => {}
+            }
+        }
+    }
+
+    fn effects(&mut self, path: &str, effects: &[Effect]) {
+        for (i, effect) in effects.iter().enumerate() {
+            let path = format!("{}[{}]", path, i);
+            match effect {
+                Effect::GiveItem(item_id) | Effect::RemoveItem(item_id) | Effect::SetItemState(item_id, _, _) => {
+                    self.item(path, item_id)
+                }
+                Effect::SpawnItem(item_id, node_id) => {
+                    self.item(path.clone(), item_id);
+                    self.node(path, node_id);
+                }
+                Effect::AddEdge(node_id, edge) => {
+                    self.node(path.clone(), node_id);
+                    self.node(format!("{}.target_id", path), &edge.target_id);
+                    self.conditions(&format!("{}.conditions", path), &edge.conditions);
+                    self.effects(&format!("{}.effects", path), &edge.effects);
+                }
+                Effect::RemoveEdge(node_id, target_id) | Effect::RevealEdge(node_id, target_id) => {
+                    self.node(path.clone(), node_id);
+                    self.node(path, target_id);
+                }
+                Effect::SetDescription(node_id, _) | Effect::Teleport(node_id) => self.node(path, node_id),
+                Effect::SetNpcAttribute(npc_id, _, _) => self.npc(path, npc_id),
+                Effect::MoveNpc(npc_id, node_id) => {
+                    self.npc(path.clone(), npc_id);
+                    self.node(path, node_id);
+                }
+                Effect::Random(outcomes) => self.outcomes(&path, outcomes),
+                Effect::RollTable(table_id) => {
+                    if !self.template.tables.contains_key(table_id) {
+                        self.report(Severity::Error, path, DiagnosticKind::UnknownTable(table_id.clone()));
+                    }
+                }
+                Effect::SetAttribute(_, _)
+                | Effect::UnsetAttribute(_)
+                | Effect::AdjustHP(_)
+                | Effect::SetFlag(_, _)
+                | Effect::AddStatus(_)
+                | Effect::RemoveStatus(_)
+                | Effect::Message(_)
+                | Effect::EndGame(_, _) => {}
+            }
+        }
+    }
+
+    fn outcomes(&mut self, path: &str, outcomes: &[Outcome]) {
+        if outcomes.iter().all(|o| o.weight == 0) {
+            self.report(Severity::Warning, path.to_string(), DiagnosticKind::NothingToRoll);
+        }
+        for (i, outcome) in outcomes.iter().enumerate() {
+            self.effects(&format!("{}[{}].effects", path, i), &outcome.effects);
+        }
+    }
+
+    fn triggers(&mut self, path: &str, triggers: &[Trigger]) {
+        for (i, trigger) in triggers.iter().enumerate() {
+            self.conditions(&format!("{}[{}].conditions", path, i), &trigger.conditions);
+            self.effects(&format!("{}[{}].effects", path, i), &trigger.effects);
+        }
+    }
+
+    fn run(mut self) -> Vec<Diagnostic> {
+        let template = self.template;
+
+        if !template.nodes.contains_key("start") {
+            self.report(Severity::Error, "nodes".to_string(), DiagnosticKind::MissingStartNode);
+        }
+
+        let mut node_ids: Vec<&String> = template.nodes.keys().collect();
+        node_ids.sort();
+        for key in node_ids {
+            let node = &template.nodes[key];
+            let base = format!("nodes.{}", key);
+            if &node.id != key {
+                self.report(
+                    Severity::Error,
+                    format!("{}.id", base),
+                    DiagnosticKind::IdMismatch { expected: key.clone(), found: node.id.clone() },
+                );
+            }
+
+            let mut targets = HashSet::new();
+            for (i, edge) in node.edges.iter().enumerate() {
+                let edge_path = format!("{}.edges[{}]", base, i);
+                self.node(format!("{}.target_id", edge_path), &edge.target_id);
+                if !targets.insert(&edge.target_id) {
+                    self.report(
+                        Severity::Warning,
+                        format!("{}.target_id", edge_path),
+                        DiagnosticKind::DuplicateEdge(edge.target_id.clone()),
+                    );
+                }
+                self.conditions(&format!("{}.conditions", edge_path), &edge.conditions);
+                self.effects(&format!("{}.effects", edge_path), &edge.effects);
+                if let Some(discovery) = &edge.hidden {
+                    if let Some(feature) = &discovery.feature {
+                        self.feature(format!("{}.hidden.feature", edge_path), node.features.values(), feature);
+                    }
+                    self.conditions(&format!("{}.hidden.conditions", edge_path), &discovery.conditions);
+                }
+                if let Some(hazard) = &edge.hazard {
+                    self.conditions(&format!("{}.hazard.conditions", edge_path), &hazard.conditions);
+                }
+            }
+
+            for (i, item_id) in node.items.ids().enumerate() {
+                self.item(format!("{}.items[{}]", base, i), item_id);
+            }
+            for (i, hidden) in node.hidden_items.iter().enumerate() {
+                let hidden_path = format!("{}.hidden_items[{}]", base, i);
+                self.item(format!("{}.item_id", hidden_path), &hidden.item_id);
+                if let Some(feature) = &hidden.feature {
+                    self.feature(format!("{}.feature", hidden_path), node.features.values(), feature);
+                }
+                self.conditions(&format!("{}.conditions", hidden_path), &hidden.conditions);
+            }
+            let mut feature_ids: Vec<&String> = node.features.keys().collect();
+            feature_ids.sort();
+            for key in feature_ids {
+                let feature = &node.features[key];
+                let feature_path = format!("{}.features.{}", base, key);
+                if &feature.id != key {
+                    self.report(
+                        Severity::Error,
+                        format!("{}.id", feature_path),
+                        DiagnosticKind::IdMismatch { expected: key.clone(), found: feature.id.clone() },
+                    );
+                }
+                self.conditions(&format!("{}.conditions", feature_path), &feature.conditions);
+                for (i, rule) in feature.on_use.iter().enumerate() {
+                    let rule_path = format!("{}.on_use[{}]", feature_path, i);
+                    if let Some(item_id) = &rule.item {
+                        self.item(format!("{}.item", rule_path), item_id);
+                    }
+                    self.conditions(&format!("{}.conditions", rule_path), &rule.conditions);
+                    self.effects(&format!("{}.effects", rule_path), &rule.effects);
+                }
+            }
+            for (i, fragment) in node.description_fragments.iter().enumerate() {
+                self.conditions(&format!("{}.description_fragments[{}].conditions", base, i), &fragment.conditions);
+            }
+            if let Some(hazard) = &node.hazard {
+                self.conditions(&format!("{}.hazard.conditions", base), &hazard.conditions);
+            }
+            self.triggers(&format!("{}.on_enter", base), &node.on_enter);
+            self.triggers(&format!("{}.on_first_enter", base), &node.on_first_enter);
+            self.triggers(&format!("{}.on_exit", base), &node.on_exit);
+            if let Some(chain) = self.teleport_cycle(key) {
+                let next = &chain[1];
+                let field = if teleports(node.on_enter.iter()).contains(&next) {
+                    "on_enter"
+                } else {
+                    "on_first_enter"
+                };
+                self.report(Severity::Warning, format!("{}.{}", base, field), DiagnosticKind::TeleportCycle(chain));
+            }
+            if let Some((x, y)) = node.position
+                && (x.unsigned_abs() > MAX_POSITION.unsigned_abs() || y.unsigned_abs() > MAX_POSITION.unsigned_abs())
+            {
+                self.report(Severity::Warning, format!("{}.position", base), DiagnosticKind::PositionOutOfRange(x, y));
+            }
+        }
+
+        let mut item_ids: Vec<&String> = template.items.keys().collect();
+        item_ids.sort();
+        for key in &item_ids {
+            let item = &template.items[*key];
+            let base = format!("items.{}", key);
+            if &item.id != *key {
+                self.report(
+                    Severity::Error,
+                    format!("{}.id", base),
+                    DiagnosticKind::IdMismatch { expected: key.to_string(), found: item.id.clone() },
+                );
+            }
+            self.effects(&format!("{}.on_pickup", base), &item.on_pickup);
+            for (i, rule) in item.use_rules.iter().enumerate() {
+                let rule_path = format!("{}.use_rules[{}]", base, i);
+                match &rule.target {
+                    Some(UseTarget::Item(item_id)) => self.item(format!("{}.target", rule_path), item_id),
+                    Some(UseTarget::Node(node_id)) => self.node(format!("{}.target", rule_path), node_id),
+                    Some(UseTarget::Feature(feature)) => self.feature(
+                        format!("{}.target", rule_path),
+                        template.nodes.values().flat_map(|n| n.features.values()),
+                        feature,
+                    ),
+                    None => {}
+                }
+                self.conditions(&format!("{}.conditions", rule_path), &rule.conditions);
+                self.effects(&format!("{}.effects", rule_path), &rule.effects);
+            }
+            if let Some(container) = &item.container {
+                for (i, item_id) in container.contents.ids().enumerate() {
+                    self.item(format!("{}.container.contents[{}]", base, i), item_id);
+                }
+                self.conditions(&format!("{}.container.unlock_conditions", base), &container.unlock_conditions);
+                if let Some(chain) = self.container_cycle(&item.id) {
+                    self.report(Severity::Error, format!("{}.container.contents", base), DiagnosticKind::ContainerCycle(chain));
+                }
+            }
+        }
+
+        let mut npc_ids: Vec<&String> = template.npcs.keys().collect();
+        npc_ids.sort();
+        for key in npc_ids {
+            let npc = &template.npcs[key];
+            let base = format!("npcs.{}", key);
+            if &npc.id != key {
+                self.report(
+                    Severity::Error,
+                    format!("{}.id", base),
+                    DiagnosticKind::IdMismatch { expected: key.clone(), found: npc.id.clone() },
+                );
+            }
+            self.node(format!("{}.node_id", base), &npc.node_id);
+            if !npc.dialogue.is_empty() && !npc.dialogue.contains_key("start") {
+                self.report(
+                    Severity::Error,
+                    format!("{}.dialogue", base),
+                    DiagnosticKind::UnknownDialogueNode("start".to_string()),
+                );
+            }
+            let mut line_ids: Vec<&String> = npc.dialogue.keys().collect();
+            line_ids.sort();
+            for line_id in line_ids {
+                for (i, choice) in npc.dialogue[line_id].choices.iter().enumerate() {
+                    let choice_path = format!("{}.dialogue.{}.choices[{}]", base, line_id, i);
+                    self.conditions(&format!("{}.conditions", choice_path), &choice.conditions);
+                    self.effects(&format!("{}.effects", choice_path), &choice.effects);
+                    if let Some(next) = choice.next.as_ref().filter(|next| !npc.dialogue.contains_key(*next)) {
+                        self.report(
+                            Severity::Error,
+                            format!("{}.next", choice_path),
+                            DiagnosticKind::UnknownDialogueNode(next.clone()),
+                        );
+                    }
+                }
+            }
+            if let Some(combat) = &npc.combat {
+                for (i, loot) in combat.loot.iter().enumerate() {
+                    self.item(format!("{}.combat.loot[{}].item_id", base, i), &loot.item_id);
+                }
+                self.effects(&format!("{}.combat.on_defeat", base), &combat.on_defeat);
+            }
+        }
+
+        let mut table_ids: Vec<&String> = template.tables.keys().collect();
+        table_ids.sort();
+        for key in table_ids {
+            self.outcomes(&format!("tables.{}", key), &template.tables[key]);
+        }
+
+        let mut recipes = HashSet::new();
+        for (i, combination) in template.combinations.iter().enumerate() {
+            let base = format!("combinations[{}]", i);
+            for (j, stack) in combination.ingredients.iter().enumerate() {
+                self.item(format!("{}.ingredients[{}]", base, j), &stack.item_id);
+            }
+            for (j, tool) in combination.tools.iter().enumerate() {
+                self.item(format!("{}.tools[{}]", base, j), tool);
+            }
+            for (j, stack) in combination.results.iter().enumerate() {
+                self.item(format!("{}.results[{}]", base, j), &stack.item_id);
+            }
+            self.conditions(&format!("{}.conditions", base), &combination.conditions);
+            if combination.ingredients.is_empty() {
+                self.report(Severity::Error, format!("{}.ingredients", base), DiagnosticKind::NoIngredients);
+            }
+
+            let mut item_ids = combination.item_ids();
+            item_ids.sort();
+            if !recipes.insert(item_ids) {
+                self.report(Severity::Warning, base, DiagnosticKind::DuplicateCombination);
+            }
+        }
+
+        for (name, endings) in [("victory", &template.victory), ("defeat", &template.defeat)] {
+            for (i, ending) in endings.iter().enumerate() {
+                let path = format!("{}[{}].conditions", name, i);
+                if ending.conditions.is_empty() {
+                    self.report(Severity::Warning, path.clone(), DiagnosticKind::EmptyEnding);
+                }
+                self.conditions(&path, &ending.conditions);
+            }
+        }
+
+        for key in item_ids {
+            if !self.item_is_obtainable(key) {
+                self.report(Severity::Warning, format!("items.{}", key), DiagnosticKind::UnusedItem(key.clone()));
+            }
+        }
+
+        self.diagnostics
+    }
+
+    /// The chain of container definitions through which `item_id` holds itself, if any.
+    fn container_cycle(&self, item_id: &str) -> Option<Vec<String>> {
+        let mut visited = HashSet::new();
+        let mut chains = vec![vec![item_id.to_string()]];
+        while let Some(chain) = chains.pop() {
+            let Some(container) = chain.last().and_then(|id| self.template.items.get(id)).and_then(|i| i.container.as_ref()) else {
+                continue;
+            };
+            for inner in container.contents.ids() {
+                let mut longer = chain.clone();
+                longer.push(inner.clone());
+                if inner == item_id {
+                    return Some(longer);
+                }
+                if visited.insert(inner) {
+                    chains.push(longer);
+                }
+            }
+        }
+        None
+    }
+
+    /// The chain of nodes through which the enter triggers of `node_id` can teleport
+    /// back to it, if any.
+    fn teleport_cycle(&self, node_id: &str) -> Option<Vec<String>> {
+        let mut visited = HashSet::new();
+        let mut chains = vec![vec![node_id.to_string()]];
+        while let Some(chain) = chains.pop() {
+            let Some(node) = chain.last().and_then(|id| self.template.nodes.get(id)) else {
+                continue;
+            };
+            for target in teleports(node.on_enter.iter().chain(&node.on_first_enter)) {
+                let mut longer = chain.clone();
+                longer.push(target.clone());
+                if target == node_id {
+                    return Some(longer);
+                }
+                if visited.insert(target) {
+                    chains.push(longer);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether the item appears anywhere it could enter play: a node, a container,
+    /// a combination result, loot or an effect that creates it, including dialogue choices.
+    fn item_is_obtainable(&self, item_id: &str) -> bool {
+        let template = self.template;
+        let mut effects: Vec<&Effect> = Vec::new();
+        for node in template.nodes.values() {
+            if node.items.ids().chain(node.hidden_items.iter().map(|h| &h.item_id)).any(|id| id == item_id) {
+                return true;
+            }
+            effects.extend(node.edges.iter().flat_map(|e| &e.effects));
+            for trigger in node.on_enter.iter().chain(&node.on_first_enter).chain(&node.on_exit) {
+                effects.extend(&trigger.effects);
+            }
+            for feature in node.features.values() {
+                effects.extend(feature.on_use.iter().flat_map(|u| &u.effects));
+            }
+        }
+        for item in template.items.values() {
+            if item.container.as_ref().is_some_and(|c| c.contents.contains(item_id)) {
+                return true;
+            }
+            effects.extend(&item.on_pickup);
+            effects.extend(item.use_rules.iter().flat_map(|r| &r.effects));
+        }
+        for npc in template.npcs.values() {
+            effects.extend(npc.dialogue.values().flat_map(|l| &l.choices).flat_map(|c| &c.effects));
+            if let Some(combat) = &npc.combat {
+                if combat.loot.iter().any(|l| l.item_id == item_id) {
+                    return true;
+                }
+                effects.extend(&combat.on_defeat);
+            }
+        }
+        effects.extend(template.tables.values().flatten().flat_map(|o| &o.effects));
+        // Effects nested in random outcomes can create items too
+        let mut i = 0;
+        while i < effects.len() {
+            if let Effect::Random(outcomes) = effects[i] {
+                effects.extend(outcomes.iter().flat_map(|o| &o.effects));
+            }
+            i += 1;
+        }
+        template.combinations.iter().any(|c| c.results.iter().any(|s| s.item_id == item_id))
+            || effects.iter().any(|effect| match effect {
+                Effect::GiveItem(id) | Effect::SpawnItem(id, _) => id == item_id,
+                _ => false,
+            })
+    }
+}
+
+/// Targets of the `Teleport` effects of `triggers`, including those of random outcomes.
+fn teleports<'a>(triggers: impl Iterator<Item = &'a Trigger>) -> Vec<&'a String> {
+    fn collect<'a>(effects: &'a [Effect], targets: &mut Vec<&'a String>) {
+        for effect in effects {
+            match effect {
+                Effect::Teleport(node_id) => targets.push(node_id),
+                Effect::Random(outcomes) => outcomes.iter().for_each(|o| collect(&o.effects, targets)),
+                _ => {}
+            }
+        }
+    }
+    let mut targets = Vec::new();
+    for trigger in triggers {
+        collect(&trigger.effects, &mut targets);
+    }
+    targets
+}
+
+impl WorldTemplate {
+    /// Checks the template for broken references and suspicious definitions.
+    /// Templates with any `Severity::Error` diagnostic should not be played.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        Validator { template: self, diagnostics: Vec::new() }.run()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EffectError, GameAction, GameEvent, GameState};
+
+    #[test]
+    fn test_world_yaml_is_valid() {
+        let content = std::fs::read_to_string("../world.yaml").expect("Failed to read world.yaml");
+        let template = WorldTemplate::from_yaml(&content).expect("Failed to parse world.yaml");
+        let errors: Vec<String> = template.validate().iter().filter(|d| d.is_error()).map(|d| d.to_string()).collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_validate_reports_paths() {
+        let yaml = r#"
+nodes:
+  begin:
+    id: begin
+    description: "Begin"
+    attributes: {}
+    edges:
+      - target_id: nowhere
+        label: "Nowhere"
+        conditions:
+          - !Any [!HasItem ghost_key]
+      - target_id: forest
+        label: "Forest"
+        conditions:
+          - !Not [!HasItem stick]
+    items: [rock]
+  forest:
+    id: woods
+    description: "Forest"
+    attributes: {}
+    edges: []
+    items: [stick, box]
+    position: [2000000000, 0]
+    on_enter:
+      - effects:
+          - !Teleport void
+items:
+  box:
+    id: box
+    name: "Box"
+    description: "A box"
+    can_pickup: true
+    container:
+      contents: [crate]
+  crate:
+    id: crate
+    name: "Crate"
+    description: "A crate"
+    can_pickup: true
+    container:
+      contents: [box]
+  stick:
+    id: stick
+    name: "Stick"
+    description: "A stick"
+    can_pickup: true
+  gem:
+    id: gem
+    name: "Gem"
+    description: "A gem"
+    can_pickup: true
+combinations:
+  - item1: stick
+    item2: stone
+    result: stick
+victory:
+  - conditions: []
+    message: "You win!"
+"#;
+        let mut template = WorldTemplate::from_yaml(yaml).unwrap();
+        // YAML cannot give `Not` two conditions, but code can
+        let not = &mut template.nodes.get_mut("begin").unwrap().edges[1].conditions[0].condition_type;
+        let ConditionType::Not(nested) = not else {
+            panic!("expected a Not condition, got {:?}", not);
+        };
+        nested.push(Condition { condition_type: ConditionType::HasItem("gem".to_string()) });
+        let diagnostics = template.validate();
+        let found: Vec<(Severity, &str)> = diagnostics.iter().map(|d| (d.severity, d.path.as_str())).collect();
+
+        assert_eq!(found, vec![
+            (Severity::Error, "nodes"),
+            (Severity::Error, "nodes.begin.edges[0].target_id"),
+            (Severity::Error, "nodes.begin.edges[0].conditions[0][0]"),
+            (Severity::Error, "nodes.begin.edges[1].conditions[0]"),
+            (Severity::Error, "nodes.begin.items[0]"),
+            (Severity::Error, "nodes.forest.id"),
+            (Severity::Error, "nodes.forest.on_enter[0].effects[0]"),
+            (Severity::Warning, "nodes.forest.position"),
+            (Severity::Error, "items.box.container.contents"),
+            (Severity::Error, "items.crate.container.contents"),
+            (Severity::Error, "combinations[0].ingredients[1]"),
+            (Severity::Warning, "victory[0].conditions"),
+            (Severity::Warning, "items.gem"),
+        ]);
+        assert_eq!(
+            diagnostics[1].to_string(),
+            "error: nodes.begin.edges[0].target_id: unknown node `nowhere`"
+        );
+        assert_eq!(diagnostics[8].kind, DiagnosticKind::ContainerCycle(vec!["box".into(), "crate".into(), "box".into()]));
+    }
+
+    #[test]
+    fn test_teleport_cycles() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A ring of stones."
+    attributes: {}
+    edges:
+      - target_id: hall
+        label: "Hall"
+        conditions: []
+    items: []
+  hall:
+    id: hall
+    description: "A hall of mirrors."
+    attributes: {}
+    edges: []
+    items: []
+    on_enter:
+      - !Teleport mirror
+  mirror:
+    id: mirror
+    description: "Behind the mirror."
+    attributes: {}
+    edges: []
+    items: []
+    on_first_enter:
+      - !Random [{ effects: [!Teleport hall] }]
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let found: Vec<(String, DiagnosticKind)> = template.validate().into_iter().map(|d| (d.path, d.kind)).collect();
+        assert_eq!(found, vec![
+            ("nodes.hall.on_enter".to_string(), DiagnosticKind::TeleportCycle(vec!["hall".into(), "mirror".into(), "hall".into()])),
+            (
+                "nodes.mirror.on_first_enter".to_string(),
+                DiagnosticKind::TeleportCycle(vec!["mirror".into(), "hall".into(), "mirror".into()]),
+            ),
+        ]);
+
+        // A first visit happens once, but a loop of on_enter triggers is given up on
+        // when played instead of overflowing the stack
+        let mut world = template.to_world();
+        let mirror = world.nodes.get_mut("mirror").unwrap();
+        mirror.on_enter = std::mem::take(&mut mirror.on_first_enter);
+        let mut state = GameState::new_with_world("Tester".to_string(), world);
+        let events = state.apply_action(GameAction::Move("hall".to_string()));
+        assert_eq!(events, vec![GameEvent::EffectFailed(EffectError::TeleportLoop("mirror".to_string()))]);
+        assert_eq!(state.player.current_node, "start");
+    }
+
+    #[test]
+    fn test_unknown_features() {
+        let yaml = r#"
+nodes:
+  start:
+    id: start
+    description: "A library."
+    attributes: {}
+    edges:
+      - target_id: vault
+        label: "Vault"
+        conditions: []
+        hidden:
+          feature: bookcase
+    items: [lamp]
+    features:
+      shelf:
+        id: shelf
+        aliases: [bookshelf]
+        description: "A dusty shelf."
+    hidden_items:
+      - item_id: lamp
+        feature: bookshelf
+      - item_id: lamp
+        feature: shelff
+  vault:
+    id: vault
+    description: "A vault."
+    attributes: {}
+    edges: []
+    items: []
+items:
+  lamp:
+    id: lamp
+    name: "Lamp"
+    description: "A lamp"
+    can_pickup: true
+    use_rules:
+      - target: !Feature shelf
+      - target: !Feature lectern
+"#;
+        let template = WorldTemplate::from_yaml(yaml).unwrap();
+        let found: Vec<(String, DiagnosticKind)> = template.validate().into_iter().map(|d| (d.path, d.kind)).collect();
+        assert_eq!(found, vec![
+            ("nodes.start.edges[0].hidden.feature".to_string(), DiagnosticKind::UnknownFeature("bookcase".into())),
+            ("nodes.start.hidden_items[1].feature".to_string(), DiagnosticKind::UnknownFeature("shelff".into())),
+            ("items.lamp.use_rules[1].target".to_string(), DiagnosticKind::UnknownFeature("lectern".into())),
+        ]);
+    }
+}
@@ -6,7 +6,7 @@ use axum::{
     Form, Router,
 };
 use dotiam_app::Repository;
-use dotiam_core::{GameState, WorldTemplate};
+use dotiam_core::{GameEvent, GameState, GameStatus, UseTarget, WorldTemplate};
 use serde::Deserialize;
 use sqlx::sqlite::SqlitePool;
 use std::sync::Arc;
@@ -28,6 +28,8 @@ struct CommandInput {
 struct IndexTemplate {
     run_id: String,
     state: GameState,
+    latest_log: usize,
+    highlighted: Vec<String>,
 }
 
 #[derive(Template)]
@@ -35,6 +37,26 @@ struct IndexTemplate {
 struct GamePartialTemplate {
     run_id: String,
     state: GameState,
+    latest_log: usize,
+    highlighted: Vec<String>,
+}
+
+/// Item ids that entered the inventory during the last command.
+fn highlighted_items(events: &[GameEvent]) -> Vec<String> {
+    events.iter()
+        .flat_map(|event| match event {
+            GameEvent::ItemPickedUp(id, _) | GameEvent::ItemReceived(id) => vec![id.clone()],
+            GameEvent::ItemTakenFrom { item_id, .. } => vec![item_id.clone()],
+            GameEvent::Combined { results, .. } => results.ids().cloned().collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// `/?seed=42` starts a run that plays out the same way every time.
+#[derive(Deserialize)]
+struct NewRunQuery {
+    seed: Option<u64>,
 }
 
 #[derive(Deserialize)]
@@ -85,6 +107,7 @@ async fn main() {
         .route("/game/{id}/command", post(command_handler))
         .route("/game/{id}/suggest", get(suggest_handler))
         .route("/game/{id}/export", get(export_handler))
+        .route("/game/{id}/export/{format}", get(graph_export_handler))
         .with_state(app_state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -93,6 +116,7 @@ async fn main() {
 }
 
 async fn root_handler(
+    Query(query): Query<NewRunQuery>,
     State(state): State<Arc<AppState>>,
 ) -> Result<Html<String>, AppError> {
     let run_id = if StdPath::new("world.yaml").exists() {
@@ -100,16 +124,18 @@ async fn root_handler(
         let template = WorldTemplate::from_yaml(&content).map_err(|e| AppError(e.to_string()))?;
         state
             .repo
-            .create_run_from_template("Adventurer".to_string(), template)
+            .create_run_from_template("Adventurer".to_string(), template, query.seed)
             .await?
     } else {
-        state.repo.create_run("Adventurer".to_string()).await?
+        state.repo.create_run("Adventurer".to_string(), query.seed).await?
     };
 
     let game_state = state.repo.load_run(&run_id).await?;
     let template = IndexTemplate {
         run_id,
+        latest_log: game_state.log.len(),
         state: game_state,
+        highlighted: vec![],
     };
     Ok(Html(template.render().map_err(|e| AppError(e.to_string()))?))
 }
@@ -122,7 +148,9 @@ async fn game_handler(
 
     let template = IndexTemplate {
         run_id: id,
+        latest_log: game_state.log.len(),
         state: game_state,
+        highlighted: vec![],
     };
     Ok(Html(template.render().map_err(|e| AppError(e.to_string()))?))
 }
@@ -134,13 +162,16 @@ async fn command_handler(
 ) -> Result<Html<String>, AppError> {
     let mut game_state = state.repo.load_run(&id).await?;
 
+    let latest_log = game_state.log.len();
     let action = game_state.parse_command(&input.command);
-    game_state.apply_action(action);
+    let events = game_state.apply_action(action);
     state.repo.save_run(&id, &game_state).await?;
 
     let template = GamePartialTemplate {
         run_id: id,
         state: game_state,
+        latest_log,
+        highlighted: highlighted_items(&events),
     };
     Ok(Html(template.render().map_err(|e| AppError(e.to_string()))?))
 }
@@ -160,6 +191,25 @@ async fn export_handler(
         .unwrap())
 }
 
+async fn graph_export_handler(
+    Path((id, format)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    let game_state = state.repo.load_run(&id).await?;
+    let template = WorldTemplate::from_world(&game_state.world);
+    let (body, content_type, filename) = match format.as_str() {
+        "dot" => (template.to_dot(), "text/vnd.graphviz", "world.dot"),
+        "mermaid" => (template.to_mermaid(), "text/plain", "world.mmd"),
+        _ => return Ok((StatusCode::NOT_FOUND, format!("Unknown export format: {}", format)).into_response()),
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", content_type)
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", filename))
+        .body(axum::body::Body::from(body))
+        .unwrap())
+}
+
 async fn suggest_handler(
     Path(id): Path<String>,
     Query(query): Query<SuggestionQuery>,
@@ -172,33 +222,108 @@ async fn suggest_handler(
         "help".to_string(),
         "look".to_string(),
         "inventory".to_string(),
+        "map".to_string(),
         "explore".to_string(),
         "pickup".to_string(),
         "drop".to_string(),
+        "open".to_string(),
+        "close".to_string(),
+        "put".to_string(),
         "use".to_string(),
-        "combine".to_string()
+        "combine".to_string(),
+        "talk".to_string(),
+        "attack".to_string(),
+        "flee".to_string(),
+        "undo".to_string()
     ];
     
-    if let Some(node) = game_state.world.nodes.get(&game_state.player.current_node) {
-        for edge in &node.edges {
-            if game_state.can_traverse(edge) {
-                suggestions.push(format!("go {}", edge.label));
-                suggestions.push(format!("go {}", edge.target_id));
-            }
+    for edge in game_state.visible_edges() {
+        if game_state.can_traverse(edge) {
+            suggestions.push(format!("go {}", edge.label));
+            suggestions.push(format!("go {}", edge.target_id));
         }
-        for item_id in &node.items {
+    }
+    if let Some(node) = game_state.world.nodes.get(&game_state.player.current_node) {
+        for item_id in node.items.ids() {
             suggestions.push(format!("pickup {}", item_id));
             suggestions.push(format!("explore {}", item_id));
         }
+        let mut features: Vec<_> = node.features.values()
+            .filter(|f| game_state.check_conditions(&f.conditions))
+            .collect();
+        features.sort_by(|a, b| a.id.cmp(&b.id));
+        for feature in features {
+            suggestions.push(format!("explore {}", feature.id));
+            for rule in &feature.on_use {
+                match &rule.item {
+                    Some(item_id) if game_state.player.inventory.contains(item_id) => {
+                        suggestions.push(format!("use {} on {}", item_id, feature.id));
+                    }
+                    None => suggestions.push(format!("use {}", feature.id)),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    for npc in game_state.npcs_here() {
+        suggestions.push(format!("talk {}", npc.id));
+        suggestions.push(format!("explore {}", npc.id));
+        if npc.combat.is_some() {
+            suggestions.push(format!("attack {}", npc.id));
+            for item_id in game_state.player.inventory.ids() {
+                if game_state.world.items.get(item_id).is_some_and(|i| i.attack > 0) {
+                    suggestions.push(format!("attack {} with {}", npc.id, item_id));
+                }
+            }
+        }
+    }
+    for number in 1..=game_state.dialogue_choices().len() {
+        suggestions.push(format!("say {}", number));
+    }
+
+    for (container_id, container) in game_state.reachable_containers() {
+        if container.open {
+            suggestions.push(format!("close {}", container_id));
+            for item_id in container.contents.ids() {
+                suggestions.push(format!("take {} from {}", item_id, container_id));
+            }
+            for item_id in game_state.player.inventory.ids().filter(|id| **id != container_id) {
+                suggestions.push(format!("put {} in {}", item_id, container_id));
+            }
+        } else {
+            suggestions.push(format!("open {}", container_id));
+        }
     }
 
-    for item_id in &game_state.player.inventory {
+    let inventory = &game_state.player.inventory;
+    for item_id in inventory.ids() {
         suggestions.push(format!("drop {}", item_id));
+        if inventory.count(item_id) > 1 {
+            suggestions.push(format!("drop 1 {}", item_id));
+        }
         suggestions.push(format!("use {}", item_id));
+        if let Some(item) = game_state.world.items.get(item_id) {
+            for rule in &item.use_rules {
+                match &rule.target {
+                    Some(UseTarget::Item(target)) | Some(UseTarget::Feature(target)) => {
+                        suggestions.push(format!("use {} on {}", item_id, target));
+                    }
+                    _ => {}
+                }
+            }
+        }
         suggestions.push(format!("explore {}", item_id));
         suggestions.push(format!("combine {}", item_id));
     }
 
+    for combination in &game_state.world.combinations {
+        if combination.ingredients.iter().all(|s| game_state.player.inventory.contains(&s.item_id)) {
+            let ingredients: Vec<&str> = combination.ingredients.iter().map(|s| s.item_id.as_str()).collect();
+            suggestions.push(format!("combine {}", ingredients.join(" ")));
+        }
+    }
+
     let filtered: Vec<String> = if input.is_empty() {
         vec![]
     } else {